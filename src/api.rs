@@ -0,0 +1,4 @@
+pub mod admin;
+pub mod error;
+pub mod evaluate;
+pub mod stream;
@@ -0,0 +1,5 @@
+pub mod auth;
+pub mod flags;
+pub mod merkle;
+pub mod segments;
+pub mod versioning;
@@ -0,0 +1,28 @@
+use crate::gossip::auth::RejectionCounts;
+use crate::gossip::state::GossipState;
+use axum::extract::State;
+use axum::{Json, Router, routing::get};
+use serde::Serialize;
+use tracing::instrument;
+
+pub fn router() -> Router<GossipState> {
+  Router::new().route("/v1/admin/auth", get(auth_handler))
+}
+
+/// Whether gossip is signed here, and how many gossip requests were refused.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthStatus {
+  pub enabled: bool,
+  pub keys: usize,
+  pub rejected: RejectionCounts,
+}
+
+#[instrument]
+pub async fn auth_handler(State(app): State<GossipState>) -> Json<AuthStatus> {
+  let auth = app.auth();
+  Json(AuthStatus {
+    enabled: auth.is_enabled(),
+    keys: auth.key_count(),
+    rejected: auth.rejections(),
+  })
+}
@@ -0,0 +1,317 @@
+use super::versioning::{check_tag, etag, tag};
+use crate::api::error::ApiError;
+use crate::crdts::last_write_wins::Entry;
+use crate::flags::flag::{Flag, FlagKey};
+use crate::flags::prerequisite::{Prerequisite, find_cycle};
+use crate::flags::rule::Rule;
+use crate::flags::target::Target;
+use crate::flags::variant::{FlagKind, Variant};
+use crate::gossip::state::GossipState;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{
+  Json, Router,
+  routing::{get, put},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument};
+use uuid::Uuid;
+
+pub fn router() -> Router<GossipState> {
+  Router::new()
+    .route("/v1/admin/flags", get(list_flags_handler))
+    .route(
+      "/v1/admin/flags/{key}",
+      get(get_flag_handler)
+        .post(create_flag_handler)
+        .put(replace_flag_handler)
+        .patch(patch_flag_handler)
+        .delete(delete_flag_handler),
+    )
+    .route("/v1/admin/flags/{key}/resolve", put(resolve_flag_handler))
+}
+
+/// A flag as the admin API shows it: the version evaluation uses, plus, in
+/// siblings mode, every concurrent edit still waiting to be resolved.
+#[derive(Debug, Clone, Serialize)]
+pub struct FlagView {
+  #[serde(flatten)]
+  pub flag: Flag,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub conflicts: Vec<Entry<Flag>>,
+}
+
+impl FlagView {
+  /// The entity tag of what the view shows. While the flag is conflicted it
+  /// covers every sibling, so that a resolution names the edits it replaces.
+  pub fn tag(&self) -> String {
+    if self.conflicts.is_empty() {
+      tag([&self.flag.updated_at])
+    } else {
+      tag(self.conflicts.iter().map(Entry::timestamp))
+    }
+  }
+}
+
+/// The writable parts of a flag; everything else is managed by the server.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FlagSpec {
+  #[serde(default)]
+  pub description: String,
+  #[serde(default)]
+  pub enabled: bool,
+  pub kind: FlagKind,
+  pub variants: Vec<Variant>,
+  pub default_variant: String,
+  #[serde(default)]
+  pub prerequisites: Vec<Prerequisite>,
+  #[serde(default)]
+  pub targets: Vec<Target>,
+  #[serde(default)]
+  pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FlagPatch {
+  pub description: Option<String>,
+  pub enabled: Option<bool>,
+  pub kind: Option<FlagKind>,
+  pub variants: Option<Vec<Variant>>,
+  pub default_variant: Option<String>,
+  pub prerequisites: Option<Vec<Prerequisite>>,
+  pub targets: Option<Vec<Target>>,
+  pub rules: Option<Vec<Rule>>,
+}
+
+impl FlagPatch {
+  fn apply(self, flag: &Flag) -> FlagSpec {
+    FlagSpec {
+      description: self.description.unwrap_or_else(|| flag.description.clone()),
+      enabled: self.enabled.unwrap_or(flag.enabled),
+      kind: self.kind.unwrap_or(flag.kind),
+      variants: self.variants.unwrap_or_else(|| flag.variants.clone()),
+      default_variant: self
+        .default_variant
+        .unwrap_or_else(|| flag.default_variant.clone()),
+      prerequisites: self
+        .prerequisites
+        .unwrap_or_else(|| flag.prerequisites.clone()),
+      targets: self.targets.unwrap_or_else(|| flag.targets.clone()),
+      rules: self.rules.unwrap_or_else(|| flag.rules.clone()),
+    }
+  }
+}
+
+#[instrument]
+pub async fn list_flags_handler(State(app): State<GossipState>) -> Json<Vec<FlagView>> {
+  let mut flags: Vec<_> = app
+    .flags()
+    .iter()
+    .into_iter()
+    .map(|(key, flag)| FlagView {
+      flag,
+      conflicts: app.flag_conflicts(&key),
+    })
+    .collect();
+  flags.sort_by(|a, b| a.flag.key.cmp(&b.flag.key));
+  Json(flags)
+}
+
+#[instrument]
+pub async fn get_flag_handler(
+  State(app): State<GossipState>,
+  Path(key): Path<FlagKey>,
+) -> Result<Response, ApiError> {
+  let conflicts = app.flag_conflicts(&key);
+  // A deletion can win a conflict; show the newest edit that did not delete.
+  let flag = match app.flags().get(&key) {
+    Some(flag) => flag,
+    None => conflicts
+      .iter()
+      .find_map(|sibling| sibling.value().cloned())
+      .ok_or_else(|| ApiError::NotFound(format!("Flag {} not found", key)))?,
+  };
+  let view = FlagView { flag, conflicts };
+  Ok((StatusCode::OK, etag(&view.tag()), Json(view)).into_response())
+}
+
+#[instrument]
+pub async fn create_flag_handler(
+  State(app): State<GossipState>,
+  Path(key): Path<FlagKey>,
+  Json(spec): Json<FlagSpec>,
+) -> Result<Response, ApiError> {
+  let _guard = app.lock_writes().await;
+  check_unconflicted(&app, &key)?;
+  if app.flags().get(&key).is_some() {
+    return Err(ApiError::Conflict(format!("Flag {} already exists", key)));
+  }
+  let flag = write_flag(&app, key, spec, None).await?;
+  Ok(flag_response(StatusCode::CREATED, &flag))
+}
+
+#[instrument]
+pub async fn replace_flag_handler(
+  State(app): State<GossipState>,
+  Path(key): Path<FlagKey>,
+  headers: HeaderMap,
+  Json(spec): Json<FlagSpec>,
+) -> Result<Response, ApiError> {
+  let _guard = app.lock_writes().await;
+  check_unconflicted(&app, &key)?;
+  let current = existing_flag(&app, &key)?;
+  check_unchanged(&key, &current, &headers)?;
+  let flag = write_flag(&app, key, spec, Some(&current)).await?;
+  Ok(flag_response(StatusCode::OK, &flag))
+}
+
+#[instrument]
+pub async fn patch_flag_handler(
+  State(app): State<GossipState>,
+  Path(key): Path<FlagKey>,
+  headers: HeaderMap,
+  Json(patch): Json<FlagPatch>,
+) -> Result<Response, ApiError> {
+  let _guard = app.lock_writes().await;
+  check_unconflicted(&app, &key)?;
+  let current = existing_flag(&app, &key)?;
+  check_unchanged(&key, &current, &headers)?;
+  let spec = patch.apply(&current);
+  let flag = write_flag(&app, key, spec, Some(&current)).await?;
+  Ok(flag_response(StatusCode::OK, &flag))
+}
+
+#[instrument]
+pub async fn delete_flag_handler(
+  State(app): State<GossipState>,
+  Path(key): Path<FlagKey>,
+  headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+  let _guard = app.lock_writes().await;
+  check_unconflicted(&app, &key)?;
+  let current = existing_flag(&app, &key)?;
+  check_unchanged(&key, &current, &headers)?;
+  app.remove_flag(&key).await;
+  info!("Deleted flag {}", key);
+  Ok(StatusCode::NO_CONTENT)
+}
+
+/// Replaces every concurrent edit of a flag with `spec`. Only allowed while
+/// the flag is conflicted, and only if `If-Match` names the siblings as they
+/// are now, so that an edit arriving after the caller looked isn't dropped.
+#[instrument]
+pub async fn resolve_flag_handler(
+  State(app): State<GossipState>,
+  Path(key): Path<FlagKey>,
+  headers: HeaderMap,
+  Json(spec): Json<FlagSpec>,
+) -> Result<Response, ApiError> {
+  let _guard = app.lock_writes().await;
+  let conflicts = app.flag_conflicts(&key);
+  if conflicts.is_empty() {
+    return Err(ApiError::Conflict(format!(
+      "Flag {} has no conflicting edits",
+      key
+    )));
+  }
+  let siblings = tag(conflicts.iter().map(Entry::timestamp));
+  check_tag(format!("Flag {}", key), &siblings, &headers)?;
+  let previous = conflicts
+    .iter()
+    .filter_map(Entry::value)
+    .max_by_key(|sibling| sibling.version);
+  let flag = write_flag(&app, key, spec, previous).await?;
+  info!(
+    "Resolved {} conflicting edits of flag {}",
+    conflicts.len(),
+    flag.key
+  );
+  Ok(flag_response(StatusCode::OK, &flag))
+}
+
+/// Refuses edits to a flag whose concurrent edits have not been resolved.
+fn check_unconflicted(app: &GossipState, key: &FlagKey) -> Result<(), ApiError> {
+  let conflicts = app.flag_conflicts(key);
+  if conflicts.is_empty() {
+    return Ok(());
+  }
+  Err(ApiError::Conflict(format!(
+    "Flag {} has {} conflicting edits; resolve them with PUT /v1/admin/flags/{}/resolve",
+    key,
+    conflicts.len(),
+    key
+  )))
+}
+
+fn existing_flag(app: &GossipState, key: &FlagKey) -> Result<Flag, ApiError> {
+  app
+    .flags()
+    .get(key)
+    .ok_or_else(|| ApiError::NotFound(format!("Flag {} not found", key)))
+}
+
+/// Rejects a write unless its `If-Match` names the flag as it is now.
+fn check_unchanged(key: &FlagKey, current: &Flag, headers: &HeaderMap) -> Result<(), ApiError> {
+  check_tag(
+    format!("Flag {}", key),
+    &tag([&current.updated_at]),
+    headers,
+  )
+}
+
+fn flag_response(status: StatusCode, flag: &Flag) -> Response {
+  (status, etag(&tag([&flag.updated_at])), Json(flag)).into_response()
+}
+
+async fn write_flag(
+  app: &GossipState,
+  key: FlagKey,
+  spec: FlagSpec,
+  previous: Option<&Flag>,
+) -> Result<Flag, ApiError> {
+  let flag = Flag {
+    key,
+    description: spec.description,
+    enabled: spec.enabled,
+    kind: spec.kind,
+    variants: spec.variants,
+    default_variant: spec.default_variant,
+    prerequisites: spec.prerequisites,
+    targets: spec.targets,
+    rules: spec.rules,
+    salt: previous.map_or_else(|| Uuid::new_v4().to_string(), |p| p.salt.clone()),
+    version: previous.map_or(1, |previous| previous.version + 1),
+    updated_at: app.now(),
+    updated_by: app.id().clone(),
+  };
+  flag.validate()?;
+  check_prerequisites(app, &flag)?;
+  app.insert_flag(flag.clone()).await;
+  info!("Wrote flag {} at version {}", flag.key, flag.version);
+  Ok(flag)
+}
+
+/// Rejects prerequisites that name a variant their flag does not have, or
+/// that would make the flag depend on itself. Prerequisites that have not
+/// replicated to this node yet are accepted as they are.
+fn check_prerequisites(app: &GossipState, flag: &Flag) -> Result<(), ApiError> {
+  for prerequisite in &flag.prerequisites {
+    if let Some(required) = app.flags().get(&prerequisite.flag)
+      && required.variant(&prerequisite.variant).is_none()
+    {
+      return Err(ApiError::BadRequest(format!(
+        "Prerequisite {} has no variant {}",
+        prerequisite.flag, prerequisite.variant
+      )));
+    }
+  }
+  if let Some(cycle) = find_cycle(flag, |key| app.flags().get(key)) {
+    let path: Vec<_> = cycle.iter().map(FlagKey::as_str).collect();
+    return Err(ApiError::BadRequest(format!(
+      "Prerequisites would form a cycle: {}",
+      path.join(" -> ")
+    )));
+  }
+  Ok(())
+}
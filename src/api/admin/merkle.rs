@@ -0,0 +1,35 @@
+use crate::crdts::merkle::{Maps, MerkleTree, hash_children};
+use crate::gossip::state::GossipState;
+use crate::node::NodeId;
+use axum::extract::State;
+use axum::{Json, Router, routing::get};
+use serde::Serialize;
+use tracing::instrument;
+
+pub fn router() -> Router<GossipState> {
+  Router::new().route("/v1/admin/merkle", get(merkle_handler))
+}
+
+/// The root hashes of this node's replica. Two nodes with the same `root`
+/// hold the same nodes, flags and segments.
+#[derive(Debug, Clone, Serialize)]
+pub struct MerkleRoots {
+  pub node_id: NodeId,
+  pub root: String,
+  pub maps: Maps<String>,
+}
+
+#[instrument]
+pub async fn merkle_handler(State(app): State<GossipState>) -> Json<MerkleRoots> {
+  let roots = app.trees().map(MerkleTree::root);
+  let root = hash_children(&roots.iter().copied().collect::<Vec<_>>());
+  Json(MerkleRoots {
+    node_id: app.id().clone(),
+    root: hex(root),
+    maps: roots.map(|root| hex(*root)),
+  })
+}
+
+fn hex(hash: u64) -> String {
+  format!("{:016x}", hash)
+}
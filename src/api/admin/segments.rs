@@ -0,0 +1,191 @@
+use super::versioning::{check_tag, etag, tag};
+use crate::api::error::ApiError;
+use crate::flags::segment::{Segment, SegmentKey, SegmentRule};
+use crate::gossip::state::GossipState;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router, routing::get};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use tracing::{info, instrument};
+
+pub fn router() -> Router<GossipState> {
+  Router::new()
+    .route("/v1/admin/segments", get(list_segments_handler))
+    .route(
+      "/v1/admin/segments/{key}",
+      get(get_segment_handler)
+        .post(create_segment_handler)
+        .put(replace_segment_handler)
+        .patch(patch_segment_handler)
+        .delete(delete_segment_handler),
+    )
+}
+
+/// The writable parts of a segment; everything else is managed by the server.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SegmentSpec {
+  #[serde(default)]
+  pub description: String,
+  #[serde(default)]
+  pub included: BTreeSet<String>,
+  #[serde(default)]
+  pub excluded: BTreeSet<String>,
+  #[serde(default)]
+  pub rules: Vec<SegmentRule>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SegmentPatch {
+  pub description: Option<String>,
+  pub included: Option<BTreeSet<String>>,
+  pub excluded: Option<BTreeSet<String>>,
+  pub rules: Option<Vec<SegmentRule>>,
+}
+
+impl SegmentPatch {
+  fn apply(self, segment: &Segment) -> SegmentSpec {
+    SegmentSpec {
+      description: self
+        .description
+        .unwrap_or_else(|| segment.description.clone()),
+      included: self.included.unwrap_or_else(|| segment.included.clone()),
+      excluded: self.excluded.unwrap_or_else(|| segment.excluded.clone()),
+      rules: self.rules.unwrap_or_else(|| segment.rules.clone()),
+    }
+  }
+}
+
+#[instrument]
+pub async fn list_segments_handler(State(app): State<GossipState>) -> Json<Vec<Segment>> {
+  let mut segments: Vec<_> = app
+    .segments()
+    .iter()
+    .into_iter()
+    .map(|(_, segment)| segment)
+    .collect();
+  segments.sort_by(|a, b| a.key.cmp(&b.key));
+  Json(segments)
+}
+
+#[instrument]
+pub async fn get_segment_handler(
+  State(app): State<GossipState>,
+  Path(key): Path<SegmentKey>,
+) -> Result<Response, ApiError> {
+  let segment = existing_segment(&app, &key)?;
+  Ok(segment_response(StatusCode::OK, &segment))
+}
+
+#[instrument]
+pub async fn create_segment_handler(
+  State(app): State<GossipState>,
+  Path(key): Path<SegmentKey>,
+  Json(spec): Json<SegmentSpec>,
+) -> Result<Response, ApiError> {
+  let _guard = app.lock_writes().await;
+  if app.segments().get(&key).is_some() {
+    return Err(ApiError::Conflict(format!(
+      "Segment {} already exists",
+      key
+    )));
+  }
+  let segment = write_segment(&app, key, spec, None).await?;
+  Ok(segment_response(StatusCode::CREATED, &segment))
+}
+
+#[instrument]
+pub async fn replace_segment_handler(
+  State(app): State<GossipState>,
+  Path(key): Path<SegmentKey>,
+  headers: HeaderMap,
+  Json(spec): Json<SegmentSpec>,
+) -> Result<Response, ApiError> {
+  let _guard = app.lock_writes().await;
+  let current = existing_segment(&app, &key)?;
+  check_unchanged(&key, &current, &headers)?;
+  let segment = write_segment(&app, key, spec, Some(&current)).await?;
+  Ok(segment_response(StatusCode::OK, &segment))
+}
+
+#[instrument]
+pub async fn patch_segment_handler(
+  State(app): State<GossipState>,
+  Path(key): Path<SegmentKey>,
+  headers: HeaderMap,
+  Json(patch): Json<SegmentPatch>,
+) -> Result<Response, ApiError> {
+  let _guard = app.lock_writes().await;
+  let current = existing_segment(&app, &key)?;
+  check_unchanged(&key, &current, &headers)?;
+  let spec = patch.apply(&current);
+  let segment = write_segment(&app, key, spec, Some(&current)).await?;
+  Ok(segment_response(StatusCode::OK, &segment))
+}
+
+#[instrument]
+pub async fn delete_segment_handler(
+  State(app): State<GossipState>,
+  Path(key): Path<SegmentKey>,
+  headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+  let _guard = app.lock_writes().await;
+  let current = existing_segment(&app, &key)?;
+  check_unchanged(&key, &current, &headers)?;
+  app.remove_segment(&key).await;
+  info!("Deleted segment {}", key);
+  Ok(StatusCode::NO_CONTENT)
+}
+
+fn existing_segment(app: &GossipState, key: &SegmentKey) -> Result<Segment, ApiError> {
+  app
+    .segments()
+    .get(key)
+    .ok_or_else(|| ApiError::NotFound(format!("Segment {} not found", key)))
+}
+
+/// Rejects a write unless its `If-Match` names the segment as it is now.
+fn check_unchanged(
+  key: &SegmentKey,
+  current: &Segment,
+  headers: &HeaderMap,
+) -> Result<(), ApiError> {
+  check_tag(
+    format!("Segment {}", key),
+    &tag([&current.updated_at]),
+    headers,
+  )
+}
+
+fn segment_response(status: StatusCode, segment: &Segment) -> Response {
+  (status, etag(&tag([&segment.updated_at])), Json(segment)).into_response()
+}
+
+async fn write_segment(
+  app: &GossipState,
+  key: SegmentKey,
+  spec: SegmentSpec,
+  previous: Option<&Segment>,
+) -> Result<Segment, ApiError> {
+  let segment = Segment {
+    key,
+    description: spec.description,
+    included: spec.included,
+    excluded: spec.excluded,
+    rules: spec.rules,
+    version: previous.map_or(1, |previous| previous.version + 1),
+    updated_at: app.now(),
+    updated_by: app.id().clone(),
+  };
+  segment.validate()?;
+  app
+    .segments()
+    .insert(segment.key.clone(), segment.clone())
+    .await;
+  info!(
+    "Wrote segment {} at version {}",
+    segment.key, segment.version
+  );
+  Ok(segment)
+}
@@ -0,0 +1,56 @@
+use crate::api::error::ApiError;
+use crate::crdts::hlc::Hlc;
+use axum::http::header::{ETAG, IF_MATCH};
+use axum::http::{HeaderMap, HeaderValue};
+use sha2::{Digest, Sha256};
+
+/// The entity tag of a write, or of a set of concurrent writes, from the
+/// clock readings they were made at. Version numbers alone won't do, as a
+/// resource deleted and created again starts over at version 1.
+pub fn tag<'a>(writes: impl IntoIterator<Item = &'a Hlc>) -> String {
+  let mut writes: Vec<_> = writes.into_iter().collect();
+  writes.sort();
+  let mut hasher = Sha256::new();
+  for at in writes {
+    hasher.update(format!("{}.{}.{}\n", at.physical_ms, at.logical, at.node));
+  }
+  hex::encode(&hasher.finalize()[..8])
+}
+
+/// The `ETag` header for a resource whose entity tag is `tag`.
+pub fn etag(tag: &str) -> [(axum::http::HeaderName, HeaderValue); 1] {
+  let value = HeaderValue::from_str(&format!("\"{}\"", tag))
+    .expect("An entity tag is always a valid header value");
+  [(ETAG, value)]
+}
+
+/// Parses the entity tag the client last saw from `If-Match`, which carries
+/// the `ETag` handed out on reads and writes.
+pub fn expected_tag(headers: &HeaderMap) -> Result<String, ApiError> {
+  let value = headers
+    .get(IF_MATCH)
+    .ok_or_else(|| ApiError::PreconditionRequired("If-Match header is required".to_string()))?;
+  value
+    .to_str()
+    .ok()
+    .map(|value| value.trim().trim_start_matches("W/").trim_matches('"'))
+    .filter(|value| !value.is_empty())
+    .map(String::from)
+    .ok_or_else(|| ApiError::BadRequest(format!("Invalid If-Match header: {:?}", value)))
+}
+
+/// Rejects a write whose `If-Match` does not name the current entity tag.
+pub fn check_tag(
+  name: impl std::fmt::Display,
+  current: &str,
+  headers: &HeaderMap,
+) -> Result<(), ApiError> {
+  let expected = expected_tag(headers)?;
+  if expected != current {
+    return Err(ApiError::Conflict(format!(
+      "{} has changed: its ETag is \"{}\", not \"{}\"",
+      name, current, expected
+    )));
+  }
+  Ok(())
+}
@@ -0,0 +1,45 @@
+use axum::Json;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde_json::json;
+
+#[derive(Debug)]
+pub enum ApiError {
+  BadRequest(String),
+  NotFound(String),
+  Conflict(String),
+  PreconditionRequired(String),
+}
+
+impl ApiError {
+  pub fn status(&self) -> StatusCode {
+    match self {
+      Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+      Self::NotFound(_) => StatusCode::NOT_FOUND,
+      Self::Conflict(_) => StatusCode::CONFLICT,
+      Self::PreconditionRequired(_) => StatusCode::PRECONDITION_REQUIRED,
+    }
+  }
+
+  pub fn message(&self) -> &str {
+    match self {
+      Self::BadRequest(message)
+      | Self::NotFound(message)
+      | Self::Conflict(message)
+      | Self::PreconditionRequired(message) => message,
+    }
+  }
+}
+
+impl From<eyre::Report> for ApiError {
+  fn from(error: eyre::Report) -> Self {
+    Self::BadRequest(error.to_string())
+  }
+}
+
+impl IntoResponse for ApiError {
+  fn into_response(self) -> Response {
+    let body = json!({"error": self.message()});
+    (self.status(), Json(body)).into_response()
+  }
+}
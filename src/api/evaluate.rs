@@ -0,0 +1,95 @@
+use crate::evaluation::context::EvaluationContext;
+use crate::evaluation::evaluator::{EvaluationResult, Evaluator};
+use crate::evaluation::reason::Reason;
+use crate::evaluation::replica::ReplicaInfo;
+use crate::flags::flag::FlagKey;
+use crate::gossip::state::GossipState;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::{
+  Json, Router,
+  routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use tracing::instrument;
+
+pub fn router() -> Router<GossipState> {
+  Router::new()
+    .route("/v1/flags/{key}/evaluate", get(evaluate_flag_handler))
+    .route("/v1/evaluate", post(evaluate_handler))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EvaluateRequest {
+  pub context: EvaluationContext,
+  /// Flags to evaluate; every flag in the replica when omitted.
+  #[serde(default)]
+  pub flags: Option<Vec<FlagKey>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EvaluateFlagResponse {
+  #[serde(flatten)]
+  pub result: EvaluationResult,
+  pub replica: ReplicaInfo,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EvaluateResponse {
+  pub flags: Vec<EvaluationResult>,
+  pub replica: ReplicaInfo,
+}
+
+/// Evaluates a single flag. The `key` query parameter is the context key and
+/// every other query parameter becomes a string attribute, which numeric and
+/// date operators read as a number where it holds one.
+#[instrument]
+pub async fn evaluate_flag_handler(
+  State(app): State<GossipState>,
+  Path(key): Path<FlagKey>,
+  Query(mut params): Query<HashMap<String, String>>,
+) -> Response {
+  let Some(context_key) = params.remove("key") else {
+    let body = json!({"error": "missing `key` query parameter"});
+    return (StatusCode::BAD_REQUEST, Json(body)).into_response();
+  };
+  let context = EvaluationContext {
+    key: context_key,
+    attributes: params
+      .into_iter()
+      .map(|(name, value)| (name, serde_json::Value::String(value)))
+      .collect(),
+  };
+  let result = Evaluator::new(&app).evaluate(&key, &context);
+  let status = match result.reason {
+    Reason::FlagNotFound => StatusCode::NOT_FOUND,
+    _ => StatusCode::OK,
+  };
+  let response = EvaluateFlagResponse {
+    result,
+    replica: ReplicaInfo::of(&app),
+  };
+  (status, Json(response)).into_response()
+}
+
+#[instrument]
+pub async fn evaluate_handler(
+  State(app): State<GossipState>,
+  Json(request): Json<EvaluateRequest>,
+) -> Json<EvaluateResponse> {
+  let evaluator = Evaluator::new(&app);
+  let flags = match request.flags {
+    Some(keys) => keys
+      .iter()
+      .map(|key| evaluator.evaluate(key, &request.context))
+      .collect(),
+    None => evaluator.evaluate_all(&request.context),
+  };
+  Json(EvaluateResponse {
+    flags,
+    replica: ReplicaInfo::of(&app),
+  })
+}
@@ -0,0 +1,57 @@
+use crate::flags::flag::Flag;
+use crate::gossip::state::GossipState;
+use axum::extract::State;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::{Extension, Router, routing::get};
+use futures::stream::{self, Stream, StreamExt};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, instrument, warn};
+
+pub fn router() -> Router<GossipState> {
+  Router::new().route("/v1/stream", get(stream_handler))
+}
+
+/// Streams flag changes as server-sent events. Every stream opens with a
+/// `snapshot` of the replica's flags, and a new snapshot is sent whenever the
+/// subscriber falls too far behind to be given the individual changes. The
+/// stream ends when the node shuts down.
+#[instrument]
+pub async fn stream_handler(
+  State(app): State<GossipState>,
+  Extension(cancel_token): Extension<CancellationToken>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+  let receiver = app.subscribe_flags();
+  let snapshot = stream::once({
+    let app = app.clone();
+    async move { snapshot_event(&app) }
+  });
+  let changes = stream::unfold((receiver, app), |(mut receiver, app)| async move {
+    let event = match receiver.recv().await {
+      Ok(event) => Event::default().event(event.name()).json_data(&event),
+      Err(RecvError::Lagged(skipped)) => {
+        warn!("Flag stream subscriber skipped {} events", skipped);
+        snapshot_event(&app)
+      },
+      Err(RecvError::Closed) => return None,
+    };
+    Some((event, (receiver, app)))
+  });
+  let events = snapshot.chain(changes).take_until(async move {
+    cancel_token.cancelled().await;
+    debug!("Closing flag stream for shutdown");
+  });
+  Sse::new(events).keep_alive(KeepAlive::new().interval(Duration::from_secs(15)))
+}
+
+fn snapshot_event(app: &GossipState) -> Result<Event, axum::Error> {
+  let mut flags: Vec<Flag> = app
+    .flags()
+    .iter()
+    .into_iter()
+    .map(|(_, flag)| flag)
+    .collect();
+  flags.sort_by(|a, b| a.key.cmp(&b.key));
+  Event::default().event("snapshot").json_data(&flags)
+}
@@ -1 +1,11 @@
+pub mod crdt;
+pub mod g_counter;
+pub mod hlc;
 pub mod last_write_wins;
+pub mod merkle;
+pub mod mv_register;
+pub mod or_set;
+pub mod pn_counter;
+pub mod tracked_map;
+pub mod tracked_mv_map;
+pub mod version_vector;
@@ -0,0 +1,43 @@
+/// A state-based replicated data type: replicas that have merged the same
+/// states hold the same value, whatever order the merges happened in.
+pub trait Crdt: Clone {
+  /// Joins `other` into `self`, returning whether `self` changed.
+  fn merge(&mut self, other: &Self) -> bool;
+
+  /// The part of `self` that `since` has not seen, as a state that can be
+  /// merged into it, or `None` if `since` is already up to date.
+  #[allow(dead_code)]
+  fn delta(&self, since: &Self) -> Option<Self>;
+}
+
+/// Checks the laws every `Crdt` has to obey on three sample states: merges
+/// commute, associate and are idempotent, and merging a delta does the same
+/// as merging the whole state it was taken from.
+#[cfg(test)]
+pub fn check_laws<T: Crdt + PartialEq + std::fmt::Debug>(a: &T, b: &T, c: &T) {
+  let merged = |x: &T, y: &T| {
+    let mut merged = x.clone();
+    merged.merge(y);
+    merged
+  };
+  assert_eq!(merged(a, b), merged(b, a), "merge is not commutative");
+  assert_eq!(
+    merged(&merged(a, b), c),
+    merged(a, &merged(b, c)),
+    "merge is not associative"
+  );
+  for x in [a, b, c] {
+    assert_eq!(merged(x, x), *x, "merge is not idempotent");
+    assert!(
+      !x.clone().merge(x),
+      "merging a state into itself changed it"
+    );
+  }
+  for (x, since) in [(a, b), (b, a), (a, c), (c, a), (b, c), (c, b)] {
+    let full = merged(since, x);
+    match x.delta(since) {
+      Some(delta) => assert_eq!(merged(since, &delta), full, "delta differs from state"),
+      None => assert_eq!(full, *since, "delta missed a change"),
+    }
+  }
+}
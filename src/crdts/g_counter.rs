@@ -0,0 +1,105 @@
+use super::crdt::Crdt;
+use crate::node::NodeId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A counter that only goes up. Each node counts its own increments, and
+/// the value is the sum over all nodes.
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
+#[allow(dead_code)]
+pub struct GCounter(BTreeMap<NodeId, u64>);
+
+#[allow(dead_code)]
+impl GCounter {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Adds `amount` on behalf of `node`, returning the delta to replicate.
+  pub fn increment(&mut self, node: &NodeId, amount: u64) -> Self {
+    if amount == 0 {
+      return Self::new();
+    }
+    let count = self.0.entry(node.clone()).or_insert(0);
+    *count += amount;
+    Self(BTreeMap::from([(node.clone(), *count)]))
+  }
+
+  pub fn value(&self) -> u64 {
+    self.0.values().sum()
+  }
+}
+
+impl Crdt for GCounter {
+  fn merge(&mut self, other: &Self) -> bool {
+    let mut changed = false;
+    // Nodes are only added with a count, so that replicas that have merged
+    // the same states hold, and hash to, the same map.
+    for (node, count) in &other.0 {
+      if *count > self.0.get(node).copied().unwrap_or(0) {
+        self.0.insert(node.clone(), *count);
+        changed = true;
+      }
+    }
+    changed
+  }
+
+  fn delta(&self, since: &Self) -> Option<Self> {
+    let missing: BTreeMap<_, _> = self
+      .0
+      .iter()
+      .filter(|(node, count)| since.0.get(*node).is_none_or(|seen| seen < *count))
+      .map(|(node, count)| (node.clone(), *count))
+      .collect();
+    (!missing.is_empty()).then_some(Self(missing))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::crdts::crdt::check_laws;
+
+  fn counter(counts: &[(&str, u64)]) -> GCounter {
+    GCounter(
+      counts
+        .iter()
+        .map(|(node, count)| (NodeId::from(*node), *count))
+        .collect(),
+    )
+  }
+
+  #[test]
+  fn merge_laws_hold() {
+    check_laws(
+      &counter(&[("a", 3), ("b", 1)]),
+      &counter(&[("b", 2), ("c", 5)]),
+      &counter(&[("a", 1), ("c", 7)]),
+    );
+  }
+
+  #[test]
+  fn merge_keeps_the_highest_count_per_node() {
+    let mut counter = counter(&[("a", 3), ("b", 1)]);
+    counter.merge(&self::counter(&[("a", 1), ("b", 4)]));
+    assert_eq!(counter.value(), 7);
+  }
+
+  #[test]
+  fn increment_returns_a_delta_that_catches_up_a_replica() {
+    let mut ours = counter(&[("a", 3), ("b", 1)]);
+    let mut theirs = ours.clone();
+    let delta = ours.increment(&NodeId::from("a"), 2);
+    theirs.merge(&delta);
+    assert_eq!(theirs, ours);
+    assert_eq!(ours.value(), 6);
+  }
+
+  #[test]
+  fn zero_counts_leave_the_state_as_it_was() {
+    let mut counter = counter(&[("a", 3)]);
+    assert!(!counter.merge(&self::counter(&[("b", 0)])));
+    assert_eq!(counter.increment(&NodeId::from("c"), 0), GCounter::new());
+    assert_eq!(counter, self::counter(&[("a", 3)]));
+  }
+}
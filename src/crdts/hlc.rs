@@ -0,0 +1,190 @@
+use crate::node::NodeId;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// A hybrid logical clock reading. Readings order by physical time, then by
+/// the logical counter, then by the node that took them, so two distinct
+/// writes never tie.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Serialize, Deserialize)]
+pub struct Hlc {
+  /// Milliseconds since the Unix epoch, as far as the clock can tell.
+  pub physical_ms: u64,
+  /// Distinguishes readings taken within the same millisecond.
+  pub logical: u32,
+  pub node: NodeId,
+}
+
+/// The clock a node stamps its writes with. It never runs backwards, and
+/// after `observe`-ing a remote reading every later local reading is newer
+/// than it, however far the local wall clock is behind. Once it has heard
+/// from a peer, readings more than `max_offset` ahead of both the local wall
+/// clock and every reading seen so far are refused, so that one node with a
+/// runaway clock can't drag every other clock along with it. Until then
+/// there is nothing to judge a reading against but a wall clock that may
+/// not have been set yet, so the first reading is adopted.
+#[derive(Debug, Clone)]
+pub struct HybridClock {
+  node: NodeId,
+  max_offset: Duration,
+  last: Arc<Mutex<Last>>,
+}
+
+/// The latest reading, and whether any of the readings so far came from a
+/// peer.
+#[derive(Debug, Default)]
+struct Last {
+  reading: (u64, u32),
+  heard: bool,
+}
+
+impl HybridClock {
+  pub fn new(node: &NodeId, max_offset: Duration) -> Self {
+    Self {
+      node: node.clone(),
+      max_offset,
+      last: Arc::new(Mutex::new(Last::default())),
+    }
+  }
+
+  /// A reading for a local write.
+  pub fn now(&self) -> Hlc {
+    let mut last = self.last.lock().expect("Clock lock poisoned");
+    let wall = wall_millis();
+    last.reading = if wall > last.reading.0 {
+      (wall, 0)
+    } else {
+      tick(last.reading)
+    };
+    self.reading(last.reading)
+  }
+
+  /// Moves the clock past a reading received from another node, unless it
+  /// is too far ahead of the local wall clock and of every reading seen.
+  pub fn observe(&self, remote: &Hlc) -> eyre::Result<()> {
+    let mut last = self.last.lock().expect("Clock lock poisoned");
+    let wall = wall_millis();
+    let max_offset = self.max_offset.as_millis() as u64;
+    let ahead = remote.physical_ms.saturating_sub(wall.max(last.reading.0));
+    if ahead > max_offset {
+      if last.heard {
+        eyre::bail!(
+          "Clock of {} is {}ms ahead, more than the {:?} allowed",
+          remote.node,
+          ahead,
+          self.max_offset
+        );
+      }
+      warn!(
+        "Adopting the clock of {}, {}ms ahead of this node's, which may not be set",
+        remote.node, ahead
+      );
+    }
+    let current = last.reading;
+    let physical_ms = wall.max(current.0).max(remote.physical_ms);
+    last.reading = if physical_ms == current.0 && physical_ms == remote.physical_ms {
+      tick((physical_ms, current.1.max(remote.logical)))
+    } else if physical_ms == current.0 {
+      tick(current)
+    } else if physical_ms == remote.physical_ms {
+      tick((physical_ms, remote.logical))
+    } else {
+      (physical_ms, 0)
+    };
+    last.heard = true;
+    Ok(())
+  }
+
+  fn reading(&self, (physical_ms, logical): (u64, u32)) -> Hlc {
+    Hlc {
+      physical_ms,
+      logical,
+      node: self.node.clone(),
+    }
+  }
+}
+
+/// The reading right after `reading`, carrying into the physical time once
+/// the logical counter runs out.
+fn tick((physical_ms, logical): (u64, u32)) -> (u64, u32) {
+  match logical.checked_add(1) {
+    Some(logical) => (physical_ms, logical),
+    None => (physical_ms.saturating_add(1), 0),
+  }
+}
+
+fn wall_millis() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .expect("Time went backwards")
+    .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+  fn clock() -> HybridClock {
+    HybridClock::new(&NodeId::from("local"), Duration::from_secs(60))
+  }
+
+  fn remote(physical_ms: u64, logical: u32) -> Hlc {
+    Hlc {
+      physical_ms,
+      logical,
+      node: NodeId::from("remote"),
+    }
+  }
+
+  #[test]
+  fn adopts_the_first_reading_however_far_behind_the_local_clock_is() {
+    let clock = clock();
+    let ahead = remote(wall_millis() + DAY_MS, 3);
+    clock.observe(&ahead).unwrap();
+    assert!(clock.now() > ahead);
+  }
+
+  #[test]
+  fn accepts_readings_close_to_those_already_seen() {
+    let clock = clock();
+    let first = wall_millis() + DAY_MS;
+    clock.observe(&remote(first, 0)).unwrap();
+    let next = remote(first + 30_000, 0);
+    clock.observe(&next).unwrap();
+    assert!(clock.now() > next);
+  }
+
+  #[test]
+  fn refuses_a_runaway_reading_once_it_has_heard_from_a_peer() {
+    let clock = clock();
+    clock.observe(&remote(wall_millis(), 0)).unwrap();
+    let runaway = remote(wall_millis() + DAY_MS, 0);
+    assert!(clock.observe(&runaway).is_err());
+    assert!(clock.now() < runaway);
+  }
+
+  #[test]
+  fn carries_an_exhausted_logical_counter_into_the_physical_time() {
+    let clock = clock();
+    let exhausted = remote(wall_millis() + 10_000, u32::MAX);
+    clock.observe(&exhausted).unwrap();
+    let next = clock.now();
+    assert!(next > exhausted);
+    assert_eq!(next.physical_ms, exhausted.physical_ms + 1);
+    assert!(clock.now() > next);
+  }
+
+  #[test]
+  fn never_runs_backwards() {
+    let clock = clock();
+    let mut previous = clock.now();
+    for _ in 0..1000 {
+      let next = clock.now();
+      assert!(next > previous);
+      previous = next;
+    }
+  }
+}
@@ -1,128 +1,220 @@
-use dashmap::DashMap;
+use super::crdt::Crdt;
+use super::hlc::Hlc;
+use super::merkle::MerkleTree;
+use super::tracked_map::{Digest, Reconciled, TrackedMap};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, hash::Hash, mem, sync::Arc};
-use tokio::sync::Mutex;
+use std::hash::Hash;
 
 pub trait LastWriteWins {
-  fn is_newer_than(&self, other: &Self) -> bool;
+  /// The clock reading the value was written at.
+  fn timestamp(&self) -> &Hlc;
+
+  fn is_newer_than(&self, other: &Self) -> bool {
+    self.timestamp() > other.timestamp()
+  }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LwwMap<K, V>
-where
-  K: Eq + Hash,
-{
-  inner: Arc<DashMap<K, V>>,
+/// A last-write-wins register: either a live value or the record that it was
+/// deleted. The tombstone is replicated like any other write, so a deletion
+/// beats every value written before it, wherever that value comes from.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Entry<V> {
+  Value(V),
+  Tombstone { deleted_at: Hlc },
 }
 
-impl<K, V> LwwMap<K, V>
+impl<V> Entry<V>
 where
-  K: Eq + Hash + Clone,
-  V: Clone + LastWriteWins,
+  V: LastWriteWins,
 {
-  pub fn new() -> Self {
-    Self {
-      inner: Arc::new(DashMap::new()),
+  pub fn value(&self) -> Option<&V> {
+    match self {
+      Self::Value(value) => Some(value),
+      Self::Tombstone { .. } => None,
     }
   }
 
-  pub fn insert(&self, key: K, value: V) {
-    match self.inner.get(&key) {
-      Some(existing) => {
-        if value.is_newer_than(existing.value()) {
-          drop(existing);
-          self.inner.insert(key, value);
-        }
-      },
-      None => {
-        self.inner.insert(key, value);
-      },
+  pub fn timestamp(&self) -> &Hlc {
+    match self {
+      Self::Value(value) => value.timestamp(),
+      Self::Tombstone { deleted_at } => deleted_at,
     }
   }
 
-  pub fn remove(&self, key: &K) {
-    self.inner.remove(key);
+  /// Ties between a value and a tombstone go to the tombstone.
+  pub fn is_newer_than(&self, other: &Self) -> bool {
+    match (self, other) {
+      (Self::Value(value), Self::Value(other)) => value.is_newer_than(other),
+      (Self::Tombstone { .. }, Self::Value(_)) => self.timestamp() >= other.timestamp(),
+      _ => self.timestamp() > other.timestamp(),
+    }
   }
+}
 
-  pub fn get(&self, key: &K) -> Option<V> {
-    self.inner.get(key).map(|v| v.value().clone())
+impl<V> Crdt for Entry<V>
+where
+  V: Clone + LastWriteWins,
+{
+  fn merge(&mut self, other: &Self) -> bool {
+    if other.is_newer_than(self) {
+      *self = other.clone();
+      return true;
+    }
+    false
   }
 
-  pub fn iter(&self) -> Vec<(K, V)> {
-    self
-      .inner
-      .iter()
-      .map(|entry| (entry.key().clone(), entry.value().clone()))
-      .collect()
+  fn delta(&self, since: &Self) -> Option<Self> {
+    self.is_newer_than(since).then(|| self.clone())
   }
 }
 
+/// A tracked map of last-write-wins registers, which reads as a map of the
+/// live values.
 #[derive(Debug, Clone)]
 pub struct TrackedLwwMap<K, V>
 where
   K: Eq + Hash,
 {
-  map: LwwMap<K, V>,
-  dirty: Arc<Mutex<HashSet<K>>>,
+  map: TrackedMap<K, Entry<V>>,
 }
 
 impl<K, V> TrackedLwwMap<K, V>
 where
-  K: Eq + Hash + Clone,
-  V: Clone + LastWriteWins,
+  K: Eq + Hash + Clone + Serialize,
+  V: Clone + LastWriteWins + Serialize,
 {
   pub fn new() -> Self {
     Self {
-      map: LwwMap::new(),
-      dirty: Arc::new(Mutex::new(HashSet::new())),
+      map: TrackedMap::new(),
     }
   }
 
-  pub async fn insert(&self, key: K, value: V) {
-    let changed = {
-      let current = self.map.get(&key);
-      match current {
-        Some(existing) => value.is_newer_than(&existing),
-        None => true,
-      }
-    };
+  /// Stores `entry` if it is newer than the current one, returning whether
+  /// it was.
+  pub async fn merge(&self, key: K, entry: Entry<V>) -> bool {
+    self.map.merge(key, entry).await
+  }
+
+  /// Inserts `value` if it is newer than what the map holds, returning
+  /// whether it was.
+  pub async fn insert(&self, key: K, value: V) -> bool {
+    self.merge(key, Entry::Value(value)).await
+  }
 
-    if changed {
-      self.map.insert(key.clone(), value);
-      let mut dirty = self.dirty.lock().await;
-      dirty.insert(key);
-    }
+  /// Replaces the entry with a tombstone, if `deleted_at` is newer than it.
+  pub async fn remove(&self, key: &K, deleted_at: Hlc) -> bool {
+    self
+      .merge(key.clone(), Entry::Tombstone { deleted_at })
+      .await
+  }
+
+  pub async fn take_dirty_batch(&self, count: usize) -> Vec<(K, Entry<V>)> {
+    self.map.take_dirty_batch(count).await
+  }
+
+  pub async fn requeue(&self, deltas: Vec<(K, Entry<V>)>) {
+    self.map.requeue(deltas).await
+  }
+
+  pub fn get(&self, key: &K) -> Option<V> {
+    self.map.get(key).and_then(|entry| entry.value().cloned())
+  }
+
+  pub fn iter(&self) -> Vec<(K, V)> {
+    self
+      .map
+      .iter()
+      .into_iter()
+      .filter_map(|(key, entry)| Some((key, entry.value()?.clone())))
+      .collect()
+  }
+
+  /// Drops tombstones written before `before` (milliseconds since the Unix
+  /// epoch), returning their keys.
+  pub fn purge_tombstones(&self, before: u64) -> Vec<K> {
+    self.map.purge(|_, entry| match entry {
+      Entry::Tombstone { deleted_at } => deleted_at.physical_ms < before,
+      Entry::Value(_) => false,
+    })
+  }
+}
+
+impl<K, V> TrackedLwwMap<K, V>
+where
+  K: Eq + Hash + Ord + Clone + Serialize,
+  V: Clone + LastWriteWins + Serialize,
+{
+  pub fn digest(&self, buckets: Option<&[usize]>) -> Digest<K> {
+    self.map.digest(buckets)
   }
 
-  pub async fn remove(&self, key: &K) {
-    self.map.remove(key);
-    let mut dirty = self.dirty.lock().await;
-    dirty.insert(key.clone());
+  pub fn reconcile(
+    &self,
+    digest: &Digest<K>,
+    buckets: Option<&[usize]>,
+  ) -> Reconciled<K, Entry<V>> {
+    self.map.reconcile(digest, buckets)
   }
 
-  pub async fn take_dirty(&self) -> HashSet<K> {
-    let mut dirty = self.dirty.lock().await;
-    mem::take(&mut *dirty)
+  pub fn tree(&self) -> &MerkleTree {
+    self.map.tree()
   }
 
-  #[allow(dead_code)]
-  pub async fn take_dirty_batch(&self, count: usize) -> HashSet<K> {
-    let mut dirty = self.dirty.lock().await;
-    let mut taken = HashSet::with_capacity(count.min(dirty.len()));
+  pub fn entries(&self, keys: &[K]) -> Vec<(K, Entry<V>)> {
+    self.map.entries(keys)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::crdts::crdt::check_laws;
+  use crate::node::NodeId;
 
-    for key in dirty.iter().take(count).cloned().collect::<Vec<_>>() {
-      dirty.remove(&key);
-      taken.insert(key);
+  #[derive(PartialEq, Debug, Clone)]
+  struct Write(Hlc);
+
+  impl LastWriteWins for Write {
+    fn timestamp(&self) -> &Hlc {
+      &self.0
     }
+  }
 
-    taken
+  fn at(physical_ms: u64) -> Hlc {
+    Hlc {
+      physical_ms,
+      logical: 0,
+      node: NodeId::from("a"),
+    }
   }
 
-  pub fn get(&self, key: &K) -> Option<V> {
-    self.map.get(key)
+  #[test]
+  fn merge_laws_hold() {
+    check_laws(
+      &Entry::Value(Write(at(1))),
+      &Entry::Tombstone { deleted_at: at(2) },
+      &Entry::Value(Write(at(3))),
+    );
   }
 
-  pub fn iter(&self) -> Vec<(K, V)> {
-    self.map.iter()
+  #[test]
+  fn newer_write_wins_whichever_side_it_is_on() {
+    let mut older = Entry::Value(Write(at(1)));
+    let newer = Entry::Value(Write(at(2)));
+    assert!(older.merge(&newer));
+    assert_eq!(older, newer);
+    let mut newer = newer;
+    assert!(!newer.merge(&Entry::Value(Write(at(1)))));
+  }
+
+  #[test]
+  fn tombstone_wins_a_tie_with_a_value() {
+    let mut value = Entry::Value(Write(at(1)));
+    let tombstone = Entry::Tombstone { deleted_at: at(1) };
+    assert!(value.merge(&tombstone));
+    assert_eq!(value, tombstone);
+    let mut tombstone = tombstone;
+    assert!(!tombstone.merge(&Entry::Value(Write(at(1)))));
   }
 }
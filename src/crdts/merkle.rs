@@ -0,0 +1,240 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Children per node of the tree.
+pub const FANOUT: usize = 16;
+/// Levels below the root. The root is level 0 and the leaves level `DEPTH`.
+pub const DEPTH: u32 = 2;
+/// The leaves, one per bucket that keys hash into.
+pub const BUCKETS: usize = FANOUT.pow(DEPTH);
+
+/// A hash tree over the entries of a map. Each leaf is the XOR of the hashes
+/// of the entries whose keys fall into its bucket, so a write updates it in
+/// place by XOR-ing the old entry out and the new one in. The levels above
+/// are hashed from their children when asked for.
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+  leaves: Arc<Vec<AtomicU64>>,
+}
+
+impl MerkleTree {
+  pub fn new() -> Self {
+    Self {
+      leaves: Arc::new((0..BUCKETS).map(|_| AtomicU64::new(0)).collect()),
+    }
+  }
+
+  /// Adds an entry's hash to its bucket, or takes it out again.
+  pub fn toggle(&self, bucket: usize, hash: u64) {
+    self.leaves[bucket].fetch_xor(hash, Ordering::Relaxed);
+  }
+
+  /// The hash of every node at `level`, in order.
+  pub fn level(&self, level: u32) -> Vec<u64> {
+    let mut hashes: Vec<u64> = self
+      .leaves
+      .iter()
+      .map(|leaf| leaf.load(Ordering::Relaxed))
+      .collect();
+    for _ in level..DEPTH {
+      hashes = hashes.chunks(FANOUT).map(hash_children).collect();
+    }
+    hashes
+  }
+
+  pub fn root(&self) -> u64 {
+    self.level(0)[0]
+  }
+
+  /// The positions and hashes of the nodes at `level` under the given
+  /// positions one level up, each listed once however often it is asked
+  /// for. At level 0 that is the root, if asked for. Positions that are not
+  /// in the tree are skipped; `check_children` tells a peer about them.
+  pub fn children(&self, level: u32, parents: &[usize]) -> Vec<(usize, u64)> {
+    if level > DEPTH {
+      return Vec::new();
+    }
+    let hashes = self.level(level);
+    if level == 0 {
+      return parents
+        .first()
+        .map(|_| (0, hashes[0]))
+        .into_iter()
+        .collect();
+    }
+    let mut parents: Vec<_> = parents
+      .iter()
+      .copied()
+      .filter(|parent| *parent < FANOUT.pow(level - 1))
+      .collect();
+    parents.sort_unstable();
+    parents.dedup();
+    parents
+      .into_iter()
+      .flat_map(|parent| parent * FANOUT..(parent + 1) * FANOUT)
+      .map(|position| (position, hashes[position]))
+      .collect()
+  }
+
+  /// The positions among a peer's nodes at `level` whose hashes differ from
+  /// this tree's: the parents to ask for the children of one level down.
+  pub fn differing(&self, level: u32, theirs: &[(usize, u64)]) -> Vec<usize> {
+    let ours = self.level(level);
+    theirs
+      .iter()
+      .filter(|(position, hash)| ours.get(*position) != Some(hash))
+      .map(|(position, _)| *position)
+      .collect()
+  }
+}
+
+/// Refuses a request for the children of `parents` at `level` that asks for
+/// nodes the tree does not have.
+pub fn check_children(level: u32, parents: &[usize]) -> eyre::Result<()> {
+  if level > DEPTH {
+    eyre::bail!("Level {} is below the leaves at level {}", level, DEPTH);
+  }
+  let width = level.checked_sub(1).map_or(1, |above| FANOUT.pow(above));
+  if let Some(parent) = parents.iter().find(|parent| **parent >= width) {
+    eyre::bail!("Level {} has no parent at {}", level, parent);
+  }
+  Ok(())
+}
+
+/// One of something for each replicated map: nodes, flags and segments.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Maps<T> {
+  pub nodes: T,
+  pub flags: T,
+  pub segments: T,
+}
+
+impl<T> Maps<T> {
+  pub fn all(value: T) -> Self
+  where
+    T: Clone,
+  {
+    Self {
+      nodes: value.clone(),
+      flags: value.clone(),
+      segments: value,
+    }
+  }
+
+  pub fn map<R>(&self, mut f: impl FnMut(&T) -> R) -> Maps<R> {
+    Maps {
+      nodes: f(&self.nodes),
+      flags: f(&self.flags),
+      segments: f(&self.segments),
+    }
+  }
+
+  pub fn zip<U, R>(&self, other: &Maps<U>, mut f: impl FnMut(&T, &U) -> R) -> Maps<R> {
+    Maps {
+      nodes: f(&self.nodes, &other.nodes),
+      flags: f(&self.flags, &other.flags),
+      segments: f(&self.segments, &other.segments),
+    }
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &T> {
+    [&self.nodes, &self.flags, &self.segments].into_iter()
+  }
+}
+
+/// The bucket `key` belongs in.
+pub fn bucket_of<K: Serialize>(key: &K) -> usize {
+  hash_of(key) as usize % BUCKETS
+}
+
+/// A platform-independent hash of a value's serialized form. Stored values
+/// serialize deterministically: their maps are all ordered.
+pub fn hash_of<T: Serialize>(value: &T) -> u64 {
+  let bytes = serde_json::to_vec(value).expect("Stored values always serialize");
+  prefix(&Sha256::digest(bytes))
+}
+
+pub fn hash_children(children: &[u64]) -> u64 {
+  let mut hasher = Sha256::new();
+  for child in children {
+    hasher.update(child.to_be_bytes());
+  }
+  prefix(&hasher.finalize())
+}
+
+fn prefix(hash: &[u8]) -> u64 {
+  let mut prefix = [0u8; 8];
+  prefix.copy_from_slice(&hash[..8]);
+  u64::from_be_bytes(prefix)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn tree(entries: &[(&str, u64)]) -> MerkleTree {
+    let tree = MerkleTree::new();
+    for (key, value) in entries {
+      tree.toggle(bucket_of(key), hash_of(&(key, value)));
+    }
+    tree
+  }
+
+  /// Walks down from the root the way anti-entropy does, returning the
+  /// leaves in which `ours` differs from `theirs`.
+  fn descend(ours: &MerkleTree, theirs: &MerkleTree) -> Vec<usize> {
+    let mut differing = vec![0];
+    for level in 0..=DEPTH {
+      check_children(level, &differing).unwrap();
+      differing = ours.differing(level, &theirs.children(level, &differing));
+    }
+    differing
+  }
+
+  #[test]
+  fn descends_to_the_one_bucket_that_differs() {
+    let entries: Vec<_> = (0..200).map(|i| (format!("key-{}", i), i)).collect();
+    let entries: Vec<_> = entries.iter().map(|(k, v)| (k.as_str(), *v)).collect();
+    let ours = tree(&entries);
+    let mut changed = entries.clone();
+    changed[42].1 = 1000;
+    let theirs = tree(&changed);
+    assert_eq!(descend(&ours, &theirs), vec![bucket_of(&"key-42")]);
+    assert_eq!(descend(&ours, &tree(&entries)), Vec::<usize>::new());
+  }
+
+  #[test]
+  fn toggling_out_and_back_in_matches_a_fresh_tree() {
+    let incremental = tree(&[("a", 1), ("b", 2), ("c", 3)]);
+    incremental.toggle(bucket_of(&"b"), hash_of(&("b", 2)) ^ hash_of(&("b", 5)));
+    incremental.toggle(bucket_of(&"c"), hash_of(&("c", 3)));
+    incremental.toggle(bucket_of(&"d"), hash_of(&("d", 4)));
+    let rebuilt = tree(&[("a", 1), ("b", 5), ("d", 4)]);
+    assert_eq!(incremental.level(DEPTH), rebuilt.level(DEPTH));
+    assert_eq!(incremental.root(), rebuilt.root());
+  }
+
+  #[test]
+  fn children_lists_each_asked_for_parent_once() {
+    let tree = tree(&[("a", 1)]);
+    let children = tree.children(DEPTH, &[3, 3, 1]);
+    let positions: Vec<_> = children.iter().map(|(position, _)| *position).collect();
+    let expected: Vec<_> = (FANOUT..2 * FANOUT).chain(3 * FANOUT..4 * FANOUT).collect();
+    assert_eq!(positions, expected);
+  }
+
+  #[test]
+  fn refuses_nodes_outside_the_tree() {
+    assert!(check_children(0, &[0]).is_ok());
+    assert!(check_children(1, &[0]).is_ok());
+    assert!(check_children(DEPTH, &[FANOUT - 1]).is_ok());
+    assert!(check_children(1, &[1]).is_err());
+    assert!(check_children(DEPTH, &[FANOUT]).is_err());
+    assert!(check_children(DEPTH, &[usize::MAX]).is_err());
+    assert!(check_children(DEPTH + 1, &[0]).is_err());
+    assert!(MerkleTree::new().children(DEPTH, &[usize::MAX]).is_empty());
+    assert!(MerkleTree::new().children(u32::MAX, &[0]).is_empty());
+  }
+}
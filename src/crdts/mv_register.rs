@@ -0,0 +1,117 @@
+use super::crdt::Crdt;
+use super::version_vector::VersionVector;
+use crate::node::NodeId;
+use serde::{Deserialize, Serialize};
+
+/// A multi-value register. Writes that happened one after another replace
+/// each other; writes that were concurrent are all kept, as siblings, until
+/// a later write that has seen them all replaces them.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct MvRegister<T> {
+  siblings: Vec<(VersionVector, T)>,
+}
+
+impl<T> Default for MvRegister<T> {
+  fn default() -> Self {
+    Self {
+      siblings: Vec::new(),
+    }
+  }
+}
+
+impl<T> MvRegister<T>
+where
+  T: Clone + PartialEq,
+{
+  /// Replaces every sibling with `value`, written by `node`, returning the
+  /// delta to replicate.
+  pub fn write(&mut self, node: &NodeId, value: T) -> Self {
+    let mut version = self.version();
+    version.increment(node);
+    self.siblings = vec![(version, value)];
+    self.clone()
+  }
+
+  pub fn values(&self) -> impl Iterator<Item = &T> {
+    self.siblings.iter().map(|(_, value)| value)
+  }
+
+  /// Every write the register has seen.
+  pub fn version(&self) -> VersionVector {
+    let mut version = VersionVector::new();
+    for (sibling, _) in &self.siblings {
+      version.join(sibling);
+    }
+    version
+  }
+}
+
+impl<T> Crdt for MvRegister<T>
+where
+  T: Clone + PartialEq,
+{
+  fn merge(&mut self, other: &Self) -> bool {
+    let mut siblings: Vec<(VersionVector, T)> = Vec::new();
+    for (version, value) in self.siblings.iter().chain(&other.siblings) {
+      let superseded = self
+        .siblings
+        .iter()
+        .chain(&other.siblings)
+        .any(|(other, _)| other != version && other.descends(version));
+      if !superseded && !siblings.iter().any(|(seen, _)| seen == version) {
+        siblings.push((version.clone(), value.clone()));
+      }
+    }
+    siblings.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let changed = siblings != self.siblings;
+    self.siblings = siblings;
+    changed
+  }
+
+  fn delta(&self, since: &Self) -> Option<Self> {
+    let mut merged = since.clone();
+    merged.merge(self).then(|| self.clone())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::crdts::crdt::check_laws;
+
+  #[test]
+  fn merge_laws_hold() {
+    let mut a = MvRegister::default();
+    a.write(&NodeId::from("a"), 1);
+    let mut b = a.clone();
+    b.write(&NodeId::from("b"), 2);
+    let mut c = a.clone();
+    c.write(&NodeId::from("c"), 3);
+    check_laws(&a, &b, &c);
+  }
+
+  #[test]
+  fn concurrent_writes_are_kept_as_siblings() {
+    let mut ours = MvRegister::default();
+    ours.write(&NodeId::from("a"), 1);
+    let mut theirs = ours.clone();
+    ours.write(&NodeId::from("a"), 2);
+    theirs.write(&NodeId::from("b"), 3);
+    ours.merge(&theirs);
+    let mut values: Vec<_> = ours.values().copied().collect();
+    values.sort();
+    assert_eq!(values, vec![2, 3]);
+  }
+
+  #[test]
+  fn a_write_that_saw_the_siblings_replaces_them() {
+    let mut ours = MvRegister::default();
+    ours.write(&NodeId::from("a"), 1);
+    let mut theirs = MvRegister::default();
+    theirs.write(&NodeId::from("b"), 2);
+    ours.merge(&theirs);
+    let delta = ours.write(&NodeId::from("a"), 3);
+    theirs.merge(&delta);
+    assert_eq!(theirs.values().copied().collect::<Vec<_>>(), vec![3]);
+  }
+}
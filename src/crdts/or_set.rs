@@ -0,0 +1,173 @@
+use super::crdt::Crdt;
+use super::hlc::Hlc;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// An observed-remove set. Every add is tagged with the clock reading it was
+/// made at, and a remove only cancels the tags it has seen, so an add that
+/// is concurrent with a remove survives it.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[allow(dead_code)]
+pub struct OrSet<T>
+where
+  T: Ord,
+{
+  added: BTreeMap<T, BTreeSet<Hlc>>,
+  removed: BTreeSet<Hlc>,
+}
+
+impl<T> Default for OrSet<T>
+where
+  T: Ord,
+{
+  fn default() -> Self {
+    Self {
+      added: BTreeMap::new(),
+      removed: BTreeSet::new(),
+    }
+  }
+}
+
+#[allow(dead_code)]
+impl<T> OrSet<T>
+where
+  T: Ord + Clone,
+{
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Adds `value` under the unique tag `at`, returning the delta to
+  /// replicate.
+  pub fn add(&mut self, value: T, at: Hlc) -> Self {
+    self
+      .added
+      .entry(value.clone())
+      .or_default()
+      .insert(at.clone());
+    Self {
+      added: BTreeMap::from([(value, BTreeSet::from([at]))]),
+      removed: BTreeSet::new(),
+    }
+  }
+
+  /// Removes every add of `value` seen so far, returning the delta to
+  /// replicate.
+  pub fn remove(&mut self, value: &T) -> Self {
+    let tags = self.added.remove(value).unwrap_or_default();
+    self.removed.extend(tags.iter().cloned());
+    Self {
+      added: BTreeMap::new(),
+      removed: tags,
+    }
+  }
+
+  pub fn contains(&self, value: &T) -> bool {
+    self.added.contains_key(value)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &T> {
+    self.added.keys()
+  }
+}
+
+impl<T> Crdt for OrSet<T>
+where
+  T: Ord + Clone,
+{
+  fn merge(&mut self, other: &Self) -> bool {
+    let mut changed = false;
+    for tag in &other.removed {
+      changed |= self.removed.insert(tag.clone());
+    }
+    for (value, tags) in &other.added {
+      let live = self.added.entry(value.clone()).or_default();
+      for tag in tags {
+        if !self.removed.contains(tag) {
+          changed |= live.insert(tag.clone());
+        }
+      }
+    }
+    let removed = &self.removed;
+    self.added.retain(|_, tags| {
+      tags.retain(|tag| !removed.contains(tag));
+      !tags.is_empty()
+    });
+    changed
+  }
+
+  fn delta(&self, since: &Self) -> Option<Self> {
+    let added: BTreeMap<_, _> = self
+      .added
+      .iter()
+      .filter_map(|(value, tags)| {
+        let seen = since.added.get(value);
+        let missing: BTreeSet<_> = tags
+          .iter()
+          .filter(|tag| seen.is_none_or(|seen| !seen.contains(*tag)))
+          .filter(|tag| !since.removed.contains(*tag))
+          .cloned()
+          .collect();
+        (!missing.is_empty()).then(|| (value.clone(), missing))
+      })
+      .collect();
+    let removed: BTreeSet<_> = self.removed.difference(&since.removed).cloned().collect();
+    if added.is_empty() && removed.is_empty() {
+      return None;
+    }
+    Some(Self { added, removed })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::crdts::crdt::check_laws;
+  use crate::node::NodeId;
+
+  fn at(physical_ms: u64, node: &str) -> Hlc {
+    Hlc {
+      physical_ms,
+      logical: 0,
+      node: NodeId::from(node),
+    }
+  }
+
+  #[test]
+  fn merge_laws_hold() {
+    let mut a = OrSet::new();
+    a.add("x", at(1, "a"));
+    a.add("y", at(2, "a"));
+    let mut b = a.clone();
+    b.remove(&"x");
+    b.add("z", at(3, "b"));
+    let mut c = OrSet::new();
+    c.add("x", at(4, "c"));
+    c.add("y", at(5, "c"));
+    check_laws(&a, &b, &c);
+  }
+
+  #[test]
+  fn concurrent_add_survives_a_remove() {
+    let mut ours = OrSet::new();
+    ours.add("x", at(1, "a"));
+    let mut theirs = ours.clone();
+    theirs.remove(&"x");
+    ours.add("x", at(2, "a"));
+    ours.merge(&theirs);
+    theirs.merge(&ours);
+    assert!(ours.contains(&"x"));
+    assert!(theirs.contains(&"x"));
+  }
+
+  #[test]
+  fn remove_beats_the_adds_it_has_seen() {
+    let mut ours = OrSet::new();
+    ours.add("x", at(1, "a"));
+    let mut theirs = ours.clone();
+    let delta = theirs.remove(&"x");
+    ours.merge(&delta);
+    assert!(!ours.contains(&"x"));
+    assert_eq!(ours, theirs);
+  }
+}
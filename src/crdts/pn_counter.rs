@@ -0,0 +1,102 @@
+use super::crdt::Crdt;
+use super::g_counter::GCounter;
+use crate::node::NodeId;
+use serde::{Deserialize, Serialize};
+
+/// A counter that goes both ways, kept as a pair of grow-only counters.
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PnCounter {
+  increments: GCounter,
+  decrements: GCounter,
+}
+
+#[allow(dead_code)]
+impl PnCounter {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Adds `amount` on behalf of `node`, returning the delta to replicate.
+  pub fn increment(&mut self, node: &NodeId, amount: u64) -> Self {
+    Self {
+      increments: self.increments.increment(node, amount),
+      decrements: GCounter::new(),
+    }
+  }
+
+  /// Subtracts `amount` on behalf of `node`, returning the delta to
+  /// replicate.
+  pub fn decrement(&mut self, node: &NodeId, amount: u64) -> Self {
+    Self {
+      increments: GCounter::new(),
+      decrements: self.decrements.increment(node, amount),
+    }
+  }
+
+  pub fn value(&self) -> i64 {
+    self.increments.value() as i64 - self.decrements.value() as i64
+  }
+}
+
+impl Crdt for PnCounter {
+  fn merge(&mut self, other: &Self) -> bool {
+    let increments = self.increments.merge(&other.increments);
+    let decrements = self.decrements.merge(&other.decrements);
+    increments || decrements
+  }
+
+  fn delta(&self, since: &Self) -> Option<Self> {
+    let increments = self.increments.delta(&since.increments);
+    let decrements = self.decrements.delta(&since.decrements);
+    if increments.is_none() && decrements.is_none() {
+      return None;
+    }
+    Some(Self {
+      increments: increments.unwrap_or_default(),
+      decrements: decrements.unwrap_or_default(),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::crdts::crdt::check_laws;
+
+  fn counter(increments: &[(&str, u64)], decrements: &[(&str, u64)]) -> PnCounter {
+    let mut counter = PnCounter::new();
+    for (node, amount) in increments {
+      counter.increment(&NodeId::from(*node), *amount);
+    }
+    for (node, amount) in decrements {
+      counter.decrement(&NodeId::from(*node), *amount);
+    }
+    counter
+  }
+
+  #[test]
+  fn merge_laws_hold() {
+    check_laws(
+      &counter(&[("a", 3)], &[("b", 1)]),
+      &counter(&[("b", 2)], &[("a", 4)]),
+      &counter(&[("a", 1), ("c", 2)], &[]),
+    );
+  }
+
+  #[test]
+  fn value_is_increments_less_decrements() {
+    let mut counter = counter(&[("a", 3)], &[("b", 1)]);
+    counter.merge(&self::counter(&[("b", 2)], &[("a", 4)]));
+    assert_eq!(counter.value(), 0);
+  }
+
+  #[test]
+  fn deltas_carry_only_what_changed() {
+    let mut ours = counter(&[("a", 3)], &[]);
+    let delta = ours.decrement(&NodeId::from("a"), 2);
+    assert_eq!(delta.value(), -2);
+    let mut theirs = counter(&[("a", 3)], &[]);
+    theirs.merge(&delta);
+    assert_eq!(theirs, ours);
+  }
+}
@@ -0,0 +1,248 @@
+use super::crdt::Crdt;
+use super::merkle::{MerkleTree, bucket_of, hash_of};
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry as MapEntry;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, hash_map};
+use std::{hash::Hash, sync::Arc};
+use tokio::sync::Mutex;
+
+/// A hash of every value in a map, by key, for comparing replicas without
+/// sending the values.
+pub type Digest<K> = BTreeMap<K, u64>;
+
+/// The entries a peer is missing, and the keys it should send back.
+pub type Reconciled<K, V> = (Vec<(K, V)>, Vec<K>);
+
+/// A map of CRDTs that remembers what changed since the last gossip round.
+/// Every change is kept as a delta, joined with earlier deltas for the same
+/// key, so a round sends only what is new rather than whole values. A Merkle
+/// tree over the entries is kept up to date with every change.
+#[derive(Debug, Clone)]
+pub struct TrackedMap<K, V>
+where
+  K: Eq + Hash,
+{
+  inner: Arc<DashMap<K, V>>,
+  dirty: Arc<Mutex<HashMap<K, V>>>,
+  tree: MerkleTree,
+}
+
+impl<K, V> TrackedMap<K, V>
+where
+  K: Eq + Hash + Clone + Serialize,
+  V: Crdt + Serialize,
+{
+  pub fn new() -> Self {
+    Self {
+      inner: Arc::new(DashMap::new()),
+      dirty: Arc::new(Mutex::new(HashMap::new())),
+      tree: MerkleTree::new(),
+    }
+  }
+
+  /// Merges `delta` into the value at `key`, whether it is a local update or
+  /// came from a peer, returning whether it changed anything.
+  pub async fn merge(&self, key: K, delta: V) -> bool {
+    let bucket = bucket_of(&key);
+    let changed = match self.inner.entry(key.clone()) {
+      MapEntry::Occupied(mut existing) => {
+        let before = hash_of(&(&key, existing.get()));
+        let changed = existing.get_mut().merge(&delta);
+        if changed {
+          self
+            .tree
+            .toggle(bucket, before ^ hash_of(&(&key, existing.get())));
+        }
+        changed
+      },
+      MapEntry::Vacant(vacant) => {
+        self.tree.toggle(bucket, hash_of(&(&key, &delta)));
+        vacant.insert(delta.clone());
+        true
+      },
+    };
+    if changed {
+      let mut dirty = self.dirty.lock().await;
+      mark_dirty(&mut dirty, key, delta);
+    }
+    changed
+  }
+
+  /// Puts deltas that could not be delivered back in the dirty set, joined
+  /// with anything that changed since they were taken.
+  pub async fn requeue(&self, deltas: Vec<(K, V)>) {
+    let mut dirty = self.dirty.lock().await;
+    for (key, delta) in deltas {
+      mark_dirty(&mut dirty, key, delta);
+    }
+  }
+
+  pub async fn take_dirty_batch(&self, count: usize) -> Vec<(K, V)> {
+    let mut dirty = self.dirty.lock().await;
+    let keys: Vec<K> = dirty.keys().take(count).cloned().collect();
+    keys
+      .into_iter()
+      .filter_map(|key| dirty.remove_entry(&key))
+      .collect()
+  }
+
+  pub fn get(&self, key: &K) -> Option<V> {
+    self.inner.get(key).map(|value| value.value().clone())
+  }
+
+  pub fn iter(&self) -> Vec<(K, V)> {
+    self
+      .inner
+      .iter()
+      .map(|entry| (entry.key().clone(), entry.value().clone()))
+      .collect()
+  }
+
+  /// Forgets every value `purge` picks out, without replicating anything,
+  /// returning their keys.
+  pub fn purge(&self, mut purge: impl FnMut(&K, &V) -> bool) -> Vec<K> {
+    let mut purged = Vec::new();
+    self.inner.retain(|key, value| {
+      if purge(key, value) {
+        self.tree.toggle(bucket_of(key), hash_of(&(key, &*value)));
+        purged.push(key.clone());
+        return false;
+      }
+      true
+    });
+    purged
+  }
+
+  pub fn tree(&self) -> &MerkleTree {
+    &self.tree
+  }
+}
+
+impl<K, V> TrackedMap<K, V>
+where
+  K: Eq + Hash + Ord + Clone + Serialize,
+  V: Crdt + Serialize,
+{
+  /// Hashes of the entries whose keys fall into `buckets`, or of all of them.
+  pub fn digest(&self, buckets: Option<&[usize]>) -> Digest<K> {
+    self
+      .inner
+      .iter()
+      .filter(|entry| in_buckets(entry.key(), buckets))
+      .map(|entry| (entry.key().clone(), hash_of(entry.value())))
+      .collect()
+  }
+
+  /// Compares the map with a peer's digest of the same buckets, returning
+  /// the entries the peer lacks or holds differently, and the keys of those
+  /// the peer holds that this map lacks or holds differently. Differing keys
+  /// appear in both, as neither side can tell from a hash whose value is
+  /// newer.
+  pub fn reconcile(&self, digest: &Digest<K>, buckets: Option<&[usize]>) -> Reconciled<K, V> {
+    let ours = self.digest(buckets);
+    let missing = ours
+      .iter()
+      .filter(|(key, hash)| digest.get(*key) != Some(*hash))
+      .filter_map(|(key, _)| Some((key.clone(), self.get(key)?)))
+      .collect();
+    let wanted = digest
+      .iter()
+      .filter(|(key, hash)| ours.get(*key) != Some(*hash))
+      .map(|(key, _)| key.clone())
+      .collect();
+    (missing, wanted)
+  }
+
+  pub fn entries(&self, keys: &[K]) -> Vec<(K, V)> {
+    keys
+      .iter()
+      .filter_map(|key| Some((key.clone(), self.get(key)?)))
+      .collect()
+  }
+}
+
+fn mark_dirty<K, V>(dirty: &mut HashMap<K, V>, key: K, delta: V)
+where
+  K: Eq + Hash,
+  V: Crdt,
+{
+  match dirty.entry(key) {
+    hash_map::Entry::Occupied(mut pending) => {
+      pending.get_mut().merge(&delta);
+    },
+    hash_map::Entry::Vacant(vacant) => {
+      vacant.insert(delta);
+    },
+  }
+}
+
+fn in_buckets<K: Serialize>(key: &K, buckets: Option<&[usize]>) -> bool {
+  buckets.is_none_or(|buckets| buckets.contains(&bucket_of(key)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::crdts::g_counter::GCounter;
+  use crate::crdts::merkle::DEPTH;
+  use crate::node::NodeId;
+
+  fn count(node: &str, amount: u64) -> GCounter {
+    GCounter::new().increment(&NodeId::from(node), amount)
+  }
+
+  /// A tree built from scratch over everything the map holds.
+  fn rebuilt(map: &TrackedMap<String, GCounter>) -> MerkleTree {
+    let tree = MerkleTree::new();
+    for (key, value) in map.iter() {
+      tree.toggle(bucket_of(&key), hash_of(&(&key, &value)));
+    }
+    tree
+  }
+
+  #[tokio::test]
+  async fn the_tree_kept_up_to_date_matches_a_rebuilt_one() {
+    let map = TrackedMap::new();
+    for i in 0..100 {
+      map
+        .merge(format!("key-{}", i % 40), count("a", i + 1))
+        .await;
+      map
+        .merge(format!("key-{}", i % 25), count("b", i + 1))
+        .await;
+    }
+    map.purge(|key, _| key.ends_with('7'));
+    assert_eq!(map.tree().level(DEPTH), rebuilt(&map).level(DEPTH));
+    assert_eq!(map.tree().root(), rebuilt(&map).root());
+  }
+
+  #[tokio::test]
+  async fn reconcile_sends_what_the_peer_lacks_and_asks_for_what_it_has() {
+    let ours = TrackedMap::new();
+    let theirs = TrackedMap::new();
+    ours.merge("shared".to_string(), count("a", 1)).await;
+    theirs.merge("shared".to_string(), count("a", 1)).await;
+    ours.merge("only-ours".to_string(), count("a", 2)).await;
+    theirs.merge("only-theirs".to_string(), count("b", 3)).await;
+    ours.merge("differs".to_string(), count("a", 4)).await;
+    theirs.merge("differs".to_string(), count("b", 5)).await;
+
+    let (missing, wanted) = ours.reconcile(&theirs.digest(None), None);
+    let missing: Vec<_> = missing.into_iter().map(|(key, _)| key).collect();
+    assert_eq!(missing, ["differs", "only-ours"]);
+    assert_eq!(wanted, ["differs", "only-theirs"]);
+  }
+
+  #[tokio::test]
+  async fn digest_covers_only_the_buckets_asked_for() {
+    let map = TrackedMap::new();
+    for i in 0..50 {
+      map.merge(format!("key-{}", i), count("a", 1)).await;
+    }
+    let bucket = bucket_of(&"key-7".to_string());
+    let digest = map.digest(Some(&[bucket]));
+    assert!(digest.contains_key("key-7"));
+    assert!(digest.keys().all(|key| bucket_of(key) == bucket));
+  }
+}
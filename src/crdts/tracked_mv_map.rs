@@ -0,0 +1,135 @@
+use super::hlc::Hlc;
+use super::last_write_wins::{Entry, LastWriteWins};
+use super::merkle::MerkleTree;
+use super::mv_register::MvRegister;
+use super::tracked_map::{Digest, Reconciled, TrackedMap};
+use crate::node::NodeId;
+use serde::Serialize;
+use std::hash::Hash;
+
+/// A tracked map of multi-value registers. Concurrent writes to a key are
+/// all kept, as siblings, until a write that has seen them replaces them.
+/// Reads pick the sibling with the newest timestamp, so every replica picks
+/// the same one.
+#[derive(Debug, Clone)]
+pub struct TrackedMvMap<K, V>
+where
+  K: Eq + Hash,
+{
+  map: TrackedMap<K, MvRegister<Entry<V>>>,
+}
+
+impl<K, V> TrackedMvMap<K, V>
+where
+  K: Eq + Hash + Clone + Serialize,
+  V: Clone + PartialEq + LastWriteWins + Serialize,
+{
+  pub fn new() -> Self {
+    Self {
+      map: TrackedMap::new(),
+    }
+  }
+
+  /// Merges a register received from a peer, returning whether it changed
+  /// anything.
+  pub async fn merge(&self, key: K, register: MvRegister<Entry<V>>) -> bool {
+    self.map.merge(key, register).await
+  }
+
+  /// Writes `entry` over every sibling this node has seen.
+  pub async fn write(&self, key: K, node: &NodeId, entry: Entry<V>) -> bool {
+    let mut register = self.map.get(&key).unwrap_or_default();
+    let delta = register.write(node, entry);
+    self.map.merge(key, delta).await
+  }
+
+  pub async fn insert(&self, key: K, node: &NodeId, value: V) -> bool {
+    self.write(key, node, Entry::Value(value)).await
+  }
+
+  pub async fn remove(&self, key: &K, node: &NodeId, deleted_at: Hlc) -> bool {
+    self
+      .write(key.clone(), node, Entry::Tombstone { deleted_at })
+      .await
+  }
+
+  pub async fn take_dirty_batch(&self, count: usize) -> Vec<(K, MvRegister<Entry<V>>)> {
+    self.map.take_dirty_batch(count).await
+  }
+
+  pub async fn requeue(&self, deltas: Vec<(K, MvRegister<Entry<V>>)>) {
+    self.map.requeue(deltas).await
+  }
+
+  /// The newest sibling, if it is not a deletion.
+  pub fn get(&self, key: &K) -> Option<V> {
+    self.map.get(key).as_ref().and_then(newest)
+  }
+
+  /// Every sibling held for `key`, newest first.
+  pub fn siblings(&self, key: &K) -> Vec<Entry<V>> {
+    let mut siblings: Vec<_> = self
+      .map
+      .get(key)
+      .map(|register| register.values().cloned().collect())
+      .unwrap_or_default();
+    siblings.sort_by(|a, b| b.timestamp().cmp(a.timestamp()));
+    siblings
+  }
+
+  pub fn iter(&self) -> Vec<(K, V)> {
+    self
+      .map
+      .iter()
+      .into_iter()
+      .filter_map(|(key, register)| Some((key, newest(&register)?)))
+      .collect()
+  }
+
+  /// Drops keys whose every sibling is a tombstone written before `before`
+  /// (milliseconds since the Unix epoch), returning them.
+  pub fn purge_tombstones(&self, before: u64) -> Vec<K> {
+    self.map.purge(|_, register| {
+      register.values().all(|entry| match entry {
+        Entry::Tombstone { deleted_at } => deleted_at.physical_ms < before,
+        Entry::Value(_) => false,
+      })
+    })
+  }
+}
+
+impl<K, V> TrackedMvMap<K, V>
+where
+  K: Eq + Hash + Ord + Clone + Serialize,
+  V: Clone + PartialEq + LastWriteWins + Serialize,
+{
+  pub fn digest(&self, buckets: Option<&[usize]>) -> Digest<K> {
+    self.map.digest(buckets)
+  }
+
+  pub fn reconcile(
+    &self,
+    digest: &Digest<K>,
+    buckets: Option<&[usize]>,
+  ) -> Reconciled<K, MvRegister<Entry<V>>> {
+    self.map.reconcile(digest, buckets)
+  }
+
+  pub fn tree(&self) -> &MerkleTree {
+    self.map.tree()
+  }
+
+  pub fn entries(&self, keys: &[K]) -> Vec<(K, MvRegister<Entry<V>>)> {
+    self.map.entries(keys)
+  }
+}
+
+fn newest<V>(register: &MvRegister<Entry<V>>) -> Option<V>
+where
+  V: Clone + PartialEq + LastWriteWins,
+{
+  register
+    .values()
+    .max_by(|a, b| a.timestamp().cmp(b.timestamp()))
+    .and_then(|entry| entry.value().cloned())
+}
@@ -0,0 +1,36 @@
+use crate::node::NodeId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Counts the writes each node has made, so that two writes can be told to
+/// be ordered or concurrent. The derived `Ord` only gives a canonical order
+/// for sorting; `descends` is the causal one.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VersionVector(BTreeMap<NodeId, u64>);
+
+impl VersionVector {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn get(&self, node: &NodeId) -> u64 {
+    self.0.get(node).copied().unwrap_or(0)
+  }
+
+  pub fn increment(&mut self, node: &NodeId) {
+    *self.0.entry(node.clone()).or_insert(0) += 1;
+  }
+
+  /// Raises every counter to the larger of the two.
+  pub fn join(&mut self, other: &Self) {
+    for (node, count) in &other.0 {
+      let entry = self.0.entry(node.clone()).or_insert(0);
+      *entry = (*entry).max(*count);
+    }
+  }
+
+  /// Whether every write `other` has seen, `self` has seen too.
+  pub fn descends(&self, other: &Self) -> bool {
+    other.0.iter().all(|(node, count)| self.get(node) >= *count)
+  }
+}
@@ -0,0 +1,3 @@
+pub mod backend;
+pub mod dns;
+pub mod seed;
@@ -0,0 +1,105 @@
+use super::dns::DnsDiscovery;
+use super::seed::SeedDiscovery;
+use crate::gossip::listener::Health;
+use crate::gossip::state::GossipState;
+use crate::gossip::tls::PeerCert;
+use crate::mdns::browser::MdnsDiscovery;
+use crate::node::NodeState;
+use crate::shutdown::container::ShutdownContainer;
+use futures::future::BoxFuture;
+use reqwest::Client;
+use std::fmt::Debug;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, instrument};
+
+/// A way of finding peers. Each backend runs as its own task until shutdown,
+/// handing whatever it finds to `GossipState::add_node`.
+pub trait Discovery: Debug + Send + Sync {
+  /// The name of the task the backend runs as.
+  fn name(&self) -> &'static str;
+
+  fn discover<'a>(
+    &'a self,
+    container: &'a ShutdownContainer,
+    cancel_token: CancellationToken,
+  ) -> BoxFuture<'a, eyre::Result<()>>;
+}
+
+/// Which discovery backends to run, and how.
+#[derive(Clone, Debug)]
+pub struct DiscoveryConfig {
+  pub mdns: bool,
+  pub seeds: Vec<String>,
+  pub dns_names: Vec<String>,
+  pub dns_resolver: Option<SocketAddr>,
+  pub dns_refresh: Duration,
+}
+
+impl DiscoveryConfig {
+  pub fn backends(&self) -> Vec<Arc<dyn Discovery>> {
+    let mut backends: Vec<Arc<dyn Discovery>> = Vec::new();
+    if self.mdns {
+      backends.push(Arc::new(MdnsDiscovery));
+    }
+    if !self.seeds.is_empty() {
+      backends.push(Arc::new(SeedDiscovery::new(self.seeds.clone())));
+    }
+    if !self.dns_names.is_empty() {
+      backends.push(Arc::new(DnsDiscovery::new(
+        self.dns_names.clone(),
+        self.dns_resolver,
+        self.dns_refresh,
+      )));
+    }
+    backends
+  }
+}
+
+/// Adds the node listening at an address found without its ID, by asking it
+/// who it is; addresses the mesh already knows are left alone.
+#[instrument]
+pub async fn add_address(
+  client: &Client,
+  app: &GossipState,
+  address: SocketAddr,
+) -> eyre::Result<()> {
+  let known = app
+    .nodes()
+    .iter()
+    .into_iter()
+    .any(|(_, state)| state.addresses().contains(&address));
+  if known {
+    return Ok(());
+  }
+  let response = client
+    .get(app.auth().url(&address.to_string(), "/health"))
+    .timeout(Duration::from_secs(1))
+    .send()
+    .await?
+    .error_for_status()?;
+  let peer = PeerCert::of_response(&response);
+  let health: Health = response.json().await?;
+  // Over TLS, the node has to hold a certificate for the ID it claims.
+  peer.check(&health.node_id)?;
+  if health.node_id == *app.id() {
+    return Ok(());
+  }
+  if health.cluster != app.cluster() {
+    debug!(
+      "Ignoring {} at {} of cluster {}",
+      health.node_id, address, health.cluster
+    );
+    return Ok(());
+  }
+  debug!("Discovered {} at {}", health.node_id, address);
+  app.record_wire(&health.node_id, health.wire());
+  let preferred = health.addresses.first().copied().unwrap_or(address);
+  let node_state =
+    NodeState::new(&health.node_id, app.now(), preferred).with_addresses(health.addresses);
+  app.add_node(&health.node_id, node_state).await;
+  app.record_route(&health.node_id, address);
+  Ok(())
+}
@@ -0,0 +1,289 @@
+use super::backend::{Discovery, add_address};
+use crate::gossip::state::GossipState;
+use crate::shutdown::container::ShutdownContainer;
+use futures::future::BoxFuture;
+use hickory_resolver::TokioResolver;
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig};
+use hickory_resolver::name_server::TokioConnectionProvider;
+use reqwest::Client;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::interval;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, instrument, warn};
+
+/// Finds peers by looking names up in DNS: `host:port` names through their
+/// A and AAAA records, anything else through its SRV records. Addresses
+/// such as `10.0.0.5:7000` or `[fd00::1]:7000` are taken as they are.
+#[derive(Debug)]
+pub struct DnsDiscovery {
+  names: Vec<String>,
+  /// The name server to ask; the system's resolver if unset.
+  resolver: Option<SocketAddr>,
+  refresh: Duration,
+}
+
+impl DnsDiscovery {
+  pub fn new(names: Vec<String>, resolver: Option<SocketAddr>, refresh: Duration) -> Self {
+    Self {
+      names,
+      resolver,
+      refresh,
+    }
+  }
+
+  fn resolver(&self) -> eyre::Result<TokioResolver> {
+    let builder = match self.resolver {
+      Some(address) => {
+        let servers = NameServerConfigGroup::from_ips_clear(&[address.ip()], address.port(), true);
+        let config = ResolverConfig::from_parts(None, vec![], servers);
+        TokioResolver::builder_with_config(config, TokioConnectionProvider::default())
+      },
+      None => TokioResolver::builder_tokio()?,
+    };
+    Ok(builder.build())
+  }
+
+  #[instrument(skip(resolver))]
+  async fn lookup(&self, resolver: &TokioResolver, name: &str) -> eyre::Result<Vec<SocketAddr>> {
+    if let Ok(address) = name.parse::<SocketAddr>() {
+      return Ok(vec![address]);
+    }
+    // A host with a colon in it is an IPv6 address without brackets, whose
+    // last group is not a port.
+    if let Some((host, port)) = name.rsplit_once(':')
+      && !host.contains(':')
+      && let Ok(port) = port.parse::<u16>()
+    {
+      let ips = resolver.lookup_ip(host).await?;
+      return Ok(ips.iter().map(|ip| SocketAddr::new(ip, port)).collect());
+    }
+    let records = resolver.srv_lookup(name).await?;
+    let mut addresses = Vec::new();
+    for record in records.iter() {
+      match resolver.lookup_ip(record.target().clone()).await {
+        Ok(ips) => addresses.extend(ips.iter().map(|ip| SocketAddr::new(ip, record.port()))),
+        Err(error) => warn!(
+          "Failed to resolve {} for {}: {}",
+          record.target(),
+          name,
+          error
+        ),
+      }
+    }
+    Ok(addresses)
+  }
+
+  #[instrument(skip(resolver))]
+  async fn refresh(&self, client: &Client, app: &GossipState, resolver: &TokioResolver) {
+    for name in &self.names {
+      let addresses = match self.lookup(resolver, name).await {
+        Ok(addresses) => addresses,
+        Err(error) => {
+          warn!("Failed to look up {}: {}", name, error);
+          continue;
+        },
+      };
+      for address in addresses {
+        if let Err(error) = add_address(client, app, address).await {
+          debug!("Peer {} from {} is unreachable: {}", address, name, error);
+        }
+      }
+    }
+  }
+}
+
+impl Discovery for DnsDiscovery {
+  fn name(&self) -> &'static str {
+    "dns_discovery"
+  }
+
+  fn discover<'a>(
+    &'a self,
+    container: &'a ShutdownContainer,
+    cancel_token: CancellationToken,
+  ) -> BoxFuture<'a, eyre::Result<()>> {
+    Box::pin(async move {
+      let resolver = self.resolver()?;
+      let mut ticker = interval(self.refresh);
+      loop {
+        tokio::select! {
+          biased;
+          _ = cancel_token.cancelled() => {
+            debug!("DNS discovery received shutdown");
+            break Ok(());
+          }
+          _ = ticker.tick() => {
+            self.refresh(&container.http_client, &container.gossip_state, &resolver).await;
+          }
+        }
+      }
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::flags::conflict::ConflictMode;
+  use crate::gossip::auth::GossipKeys;
+  use crate::gossip::listener::health_handler;
+  use crate::gossip::state::DEFAULT_CLUSTER;
+  use crate::node::NodeId;
+  use axum::{Router, routing::get};
+  use hickory_proto::op::{Message, MessageType};
+  use hickory_proto::rr::rdata::{A, SRV};
+  use hickory_proto::rr::{Name, RData, Record};
+  use std::net::Ipv4Addr;
+  use tokio::net::{TcpListener, UdpSocket};
+
+  fn state(id: &str) -> GossipState {
+    GossipState::new(
+      &NodeId::from(id),
+      DEFAULT_CLUSTER,
+      ConflictMode::Lww,
+      GossipKeys::default(),
+      None,
+      Duration::from_secs(60),
+    )
+  }
+
+  /// Runs a node that only answers health checks, as `id`.
+  async fn serve_peer(id: &str) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let app = state(id);
+    app.add_self(&[address]).await;
+    let router = Router::new()
+      .route("/health", get(health_handler))
+      .with_state(app);
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    address
+  }
+
+  /// Runs a name server that answers from `records` alone.
+  async fn serve_dns(records: Vec<Record>) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+      let mut buffer = [0; 512];
+      while let Ok((length, from)) = socket.recv_from(&mut buffer).await {
+        let Ok(query) = Message::from_vec(&buffer[..length]) else {
+          continue;
+        };
+        let mut response = Message::new();
+        response
+          .set_id(query.id())
+          .set_message_type(MessageType::Response)
+          .set_op_code(query.op_code())
+          .set_recursion_desired(query.recursion_desired())
+          .set_recursion_available(true)
+          .add_queries(query.queries().to_vec());
+        for question in query.queries() {
+          let answers = records.iter().filter(|record| {
+            record.name() == question.name() && record.record_type() == question.query_type()
+          });
+          response.add_answers(answers.cloned());
+        }
+        let _ = socket.send_to(&response.to_vec().unwrap(), from).await;
+      }
+    });
+    address
+  }
+
+  fn name(name: &str) -> Name {
+    Name::from_ascii(name).unwrap()
+  }
+
+  fn a(host: &str) -> Record {
+    Record::from_rdata(name(host), 60, RData::A(A(Ipv4Addr::LOCALHOST)))
+  }
+
+  fn srv(service: &str, host: &str, port: u16) -> Record {
+    Record::from_rdata(
+      name(service),
+      60,
+      RData::SRV(SRV::new(0, 0, port, name(host))),
+    )
+  }
+
+  async fn discover(names: &[String], records: Vec<Record>) -> GossipState {
+    let resolver = serve_dns(records).await;
+    let discovery = DnsDiscovery::new(names.to_vec(), Some(resolver), Duration::from_secs(30));
+    let app = state("self");
+    let client = Client::new();
+    discovery
+      .refresh(&client, &app, &discovery.resolver().unwrap())
+      .await;
+    app
+  }
+
+  fn addresses(app: &GossipState, id: &str) -> Option<Vec<SocketAddr>> {
+    app
+      .nodes()
+      .get(&NodeId::from(id))
+      .map(|state| state.addresses())
+  }
+
+  #[tokio::test]
+  async fn srv_names_add_every_target() {
+    let first = serve_peer("peer-1").await;
+    let second = serve_peer("peer-2").await;
+    let records = vec![
+      srv("_flags._tcp.mesh.test.", "peers.mesh.test.", first.port()),
+      srv("_flags._tcp.mesh.test.", "peers.mesh.test.", second.port()),
+      a("peers.mesh.test."),
+    ];
+    let app = discover(&["_flags._tcp.mesh.test".to_string()], records).await;
+    assert_eq!(addresses(&app, "peer-1"), Some(vec![first]));
+    assert_eq!(addresses(&app, "peer-2"), Some(vec![second]));
+  }
+
+  #[tokio::test]
+  async fn host_and_port_names_use_address_records() {
+    let peer = serve_peer("peer-1").await;
+    let names = [format!("peers.mesh.test:{}", peer.port())];
+    let app = discover(&names, vec![a("peers.mesh.test.")]).await;
+    assert_eq!(addresses(&app, "peer-1"), Some(vec![peer]));
+  }
+
+  #[tokio::test]
+  async fn addresses_are_taken_as_they_are() {
+    let discovery = DnsDiscovery::new(
+      vec![],
+      Some(serve_dns(vec![]).await),
+      Duration::from_secs(30),
+    );
+    let resolver = discovery.resolver().unwrap();
+    for name in ["[fd00::1]:7000", "10.0.0.5:7000", "[fe80::1%2]:7000"] {
+      let addresses = discovery.lookup(&resolver, name).await.unwrap();
+      assert_eq!(addresses, vec![name.parse().unwrap()], "{}", name);
+    }
+  }
+
+  #[tokio::test]
+  async fn ipv6_addresses_without_a_port_are_not_split() {
+    let discovery = DnsDiscovery::new(
+      vec![],
+      Some(serve_dns(vec![]).await),
+      Duration::from_secs(30),
+    );
+    let resolver = discovery.resolver().unwrap();
+    let addresses = discovery.lookup(&resolver, "fd00::1").await;
+    assert_eq!(addresses.unwrap_or_default(), Vec::new());
+  }
+
+  #[tokio::test]
+  async fn addresses_reach_the_peer_without_any_records() {
+    let peer = serve_peer("peer-1").await;
+    let app = discover(&[peer.to_string()], vec![]).await;
+    assert_eq!(addresses(&app, "peer-1"), Some(vec![peer]));
+  }
+
+  #[tokio::test]
+  async fn unknown_names_add_nothing() {
+    let names = ["_flags._tcp.nowhere.test".to_string()];
+    let app = discover(&names, vec![a("peers.mesh.test.")]).await;
+    assert_eq!(app.nodes().iter().len(), 0);
+  }
+}
@@ -0,0 +1,69 @@
+use super::backend::{Discovery, add_address};
+use crate::shutdown::container::ShutdownContainer;
+use futures::future::BoxFuture;
+use std::time::Duration;
+use tokio::net::lookup_host;
+use tokio::time::interval;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, instrument, warn};
+
+/// How often the seeds are contacted again, in case the node lost touch with
+/// the mesh or a seed came up late.
+const SEED_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Finds peers from a fixed list of `host:port` seeds.
+#[derive(Debug)]
+pub struct SeedDiscovery {
+  seeds: Vec<String>,
+}
+
+impl SeedDiscovery {
+  pub fn new(seeds: Vec<String>) -> Self {
+    Self { seeds }
+  }
+
+  #[instrument]
+  async fn contact_seeds(&self, container: &ShutdownContainer) {
+    for seed in &self.seeds {
+      let addresses = match lookup_host(seed.as_str()).await {
+        Ok(addresses) => addresses,
+        Err(error) => {
+          warn!("Failed to resolve seed {}: {}", seed, error);
+          continue;
+        },
+      };
+      for address in addresses {
+        let added = add_address(&container.http_client, &container.gossip_state, address).await;
+        if let Err(error) = added {
+          debug!("Seed {} at {} is unreachable: {}", seed, address, error);
+        }
+      }
+    }
+  }
+}
+
+impl Discovery for SeedDiscovery {
+  fn name(&self) -> &'static str {
+    "seed_discovery"
+  }
+
+  fn discover<'a>(
+    &'a self,
+    container: &'a ShutdownContainer,
+    cancel_token: CancellationToken,
+  ) -> BoxFuture<'a, eyre::Result<()>> {
+    Box::pin(async move {
+      let mut ticker = interval(SEED_INTERVAL);
+      loop {
+        tokio::select! {
+          biased;
+          _ = cancel_token.cancelled() => {
+            debug!("Seed discovery received shutdown");
+            break Ok(());
+          }
+          _ = ticker.tick() => self.contact_seeds(container).await,
+        }
+      }
+    })
+  }
+}
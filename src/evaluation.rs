@@ -0,0 +1,4 @@
+pub mod context;
+pub mod evaluator;
+pub mod reason;
+pub mod replica;
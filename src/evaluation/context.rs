@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// The subject of an evaluation: a user key plus whatever attributes the
+/// caller knows about them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EvaluationContext {
+  pub key: String,
+  #[serde(default)]
+  pub attributes: HashMap<String, Value>,
+}
+
+impl EvaluationContext {
+  /// Looks up an attribute by name; `key` always refers to the context key.
+  pub fn attribute(&self, name: &str) -> Option<Value> {
+    match name {
+      "key" => Some(Value::String(self.key.clone())),
+      _ => self.attributes.get(name).cloned(),
+    }
+  }
+}
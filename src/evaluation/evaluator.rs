@@ -0,0 +1,307 @@
+use super::context::EvaluationContext;
+use super::reason::{ErrorKind, Reason};
+use crate::flags::flag::{Flag, FlagKey};
+use crate::flags::rule::Serve;
+use crate::flags::variant::{FlagKind, VariantValue};
+use crate::gossip::state::GossipState;
+use serde::Serialize;
+use tracing::{instrument, trace};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EvaluationResult {
+  pub flag_key: FlagKey,
+  pub kind: Option<FlagKind>,
+  pub variant: Option<String>,
+  pub value: Option<VariantValue>,
+  pub reason: Reason,
+  /// The version of the flag that was evaluated, if it was found.
+  pub flag_version: Option<u64>,
+  /// Milliseconds since the Unix epoch at which that version was written.
+  pub flag_updated_at: Option<u64>,
+}
+
+impl EvaluationResult {
+  fn not_found(flag_key: &FlagKey) -> Self {
+    Self {
+      flag_key: flag_key.clone(),
+      kind: None,
+      variant: None,
+      value: None,
+      reason: Reason::FlagNotFound,
+      flag_version: None,
+      flag_updated_at: None,
+    }
+  }
+
+  fn error(flag: &Flag, error_kind: ErrorKind) -> Self {
+    Self {
+      flag_key: flag.key.clone(),
+      kind: Some(flag.kind),
+      variant: None,
+      value: None,
+      reason: Reason::Error { error_kind },
+      flag_version: Some(flag.version),
+      flag_updated_at: Some(flag.updated_at.physical_ms),
+    }
+  }
+}
+
+/// Evaluates flags against the local replica held in `GossipState`.
+#[derive(Debug)]
+pub struct Evaluator<'a> {
+  state: &'a GossipState,
+}
+
+impl<'a> Evaluator<'a> {
+  pub fn new(state: &'a GossipState) -> Self {
+    Self { state }
+  }
+
+  #[instrument(skip(self))]
+  pub fn evaluate(&self, key: &FlagKey, context: &EvaluationContext) -> EvaluationResult {
+    trace!("Evaluating flag {} for {}", key, context.key);
+    match self.state.flags().get(key) {
+      Some(flag) => self.evaluate_flag(&flag, context, &mut Vec::new()),
+      None => EvaluationResult::not_found(key),
+    }
+  }
+
+  pub fn evaluate_all(&self, context: &EvaluationContext) -> Vec<EvaluationResult> {
+    let mut keys: Vec<_> = self
+      .state
+      .flags()
+      .iter()
+      .into_iter()
+      .map(|(key, _)| key)
+      .collect();
+    keys.sort();
+    keys.iter().map(|key| self.evaluate(key, context)).collect()
+  }
+
+  /// `visiting` holds the chain of flags whose prerequisites led here, so
+  /// that a cycle that slipped past the admin API (say, two halves written on
+  /// different nodes) ends in an error instead of unbounded recursion.
+  fn evaluate_flag(
+    &self,
+    flag: &Flag,
+    context: &EvaluationContext,
+    visiting: &mut Vec<FlagKey>,
+  ) -> EvaluationResult {
+    if !flag.enabled {
+      return serve(flag, &flag.default_variant, Reason::Off);
+    }
+    if visiting.contains(&flag.key) {
+      return EvaluationResult::error(flag, ErrorKind::PrerequisiteCycle);
+    }
+    visiting.push(flag.key.clone());
+    let failure = self.check_prerequisites(flag, context, visiting);
+    visiting.pop();
+    if let Some(reason) = failure {
+      return match reason {
+        Reason::Error { error_kind } => EvaluationResult::error(flag, error_kind),
+        reason => serve(flag, &flag.default_variant, reason),
+      };
+    }
+    if let Some(target) = flag
+      .targets
+      .iter()
+      .find(|target| target.keys.contains(&context.key))
+    {
+      return serve(flag, &target.variant, Reason::TargetMatch);
+    }
+    for (rule_index, rule) in flag.rules.iter().enumerate() {
+      if rule.matches(context, self.state) {
+        let reason = Reason::RuleMatch {
+          rule_index,
+          rule_id: rule.id.clone(),
+        };
+        return match &rule.serve {
+          Serve::Variant(variant_id) => serve(flag, variant_id, reason),
+          Serve::Rollout(rollout) => {
+            let variant_id = rollout.variant_for(flag.key.as_str(), &flag.salt, context);
+            serve(flag, variant_id, reason)
+          },
+        };
+      }
+    }
+    serve(flag, &flag.default_variant, Reason::Fallthrough)
+  }
+
+  /// Returns the reason evaluation cannot go past the flag's prerequisites,
+  /// if there is one.
+  fn check_prerequisites(
+    &self,
+    flag: &Flag,
+    context: &EvaluationContext,
+    visiting: &mut Vec<FlagKey>,
+  ) -> Option<Reason> {
+    for prerequisite in &flag.prerequisites {
+      let Some(required) = self.state.flags().get(&prerequisite.flag) else {
+        return Some(Reason::PrerequisiteFailed {
+          prerequisite_key: prerequisite.flag.clone(),
+          not_replicated: true,
+        });
+      };
+      let result = self.evaluate_flag(&required, context, visiting);
+      if let Reason::Error {
+        error_kind: ErrorKind::PrerequisiteCycle,
+      } = result.reason
+      {
+        return Some(result.reason);
+      }
+      if !required.enabled || result.variant.as_deref() != Some(prerequisite.variant.as_str()) {
+        return Some(Reason::PrerequisiteFailed {
+          prerequisite_key: prerequisite.flag.clone(),
+          not_replicated: false,
+        });
+      }
+    }
+    None
+  }
+}
+
+fn serve(flag: &Flag, variant_id: &str, reason: Reason) -> EvaluationResult {
+  match flag.variant(variant_id) {
+    Some(variant) => EvaluationResult {
+      flag_key: flag.key.clone(),
+      kind: Some(flag.kind),
+      variant: Some(variant.id.clone()),
+      value: Some(variant.value.clone()),
+      reason,
+      flag_version: Some(flag.version),
+      flag_updated_at: Some(flag.updated_at.physical_ms),
+    },
+    None => EvaluationResult::error(flag, ErrorKind::MalformedFlag),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::flags::conflict::ConflictMode;
+  use crate::gossip::auth::GossipKeys;
+  use crate::gossip::state::DEFAULT_CLUSTER;
+  use crate::node::NodeId;
+  use serde_json::json;
+  use std::time::Duration;
+
+  /// An enabled flag that serves `on` and requires each of `prerequisites`
+  /// to serve the given variant.
+  fn flag(key: &str, prerequisites: &[(&str, &str)]) -> Flag {
+    let prerequisites: Vec<_> = prerequisites
+      .iter()
+      .map(|(flag, variant)| json!({"flag": flag, "variant": variant}))
+      .collect();
+    serde_json::from_value(json!({
+      "key": key,
+      "description": "",
+      "enabled": true,
+      "kind": "boolean",
+      "variants": [{"id": "on", "value": true}, {"id": "off", "value": false}],
+      "default_variant": "on",
+      "prerequisites": prerequisites,
+      "rules": [{"id": "everyone", "serve": {"variant": "on"}}],
+      "version": 1,
+      "updated_at": {"physical_ms": 1, "logical": 0, "node": "a"},
+      "updated_by": "a",
+    }))
+    .unwrap()
+  }
+
+  fn disabled(flag: Flag) -> Flag {
+    Flag {
+      enabled: false,
+      ..flag
+    }
+  }
+
+  async fn evaluate(flags: Vec<Flag>) -> EvaluationResult {
+    let app = GossipState::new(
+      &NodeId::from("a"),
+      DEFAULT_CLUSTER,
+      ConflictMode::Lww,
+      GossipKeys::default(),
+      None,
+      Duration::from_secs(60),
+    );
+    let key = flags[0].key.clone();
+    for flag in flags {
+      app.insert_flag(flag).await;
+    }
+    let context = EvaluationContext {
+      key: "user-1".to_string(),
+      ..Default::default()
+    };
+    Evaluator::new(&app).evaluate(&key, &context)
+  }
+
+  fn failed(prerequisite: &str, not_replicated: bool) -> Reason {
+    Reason::PrerequisiteFailed {
+      prerequisite_key: FlagKey::from(prerequisite),
+      not_replicated,
+    }
+  }
+
+  fn cycle() -> Reason {
+    Reason::Error {
+      error_kind: ErrorKind::PrerequisiteCycle,
+    }
+  }
+
+  #[tokio::test]
+  async fn met_prerequisites_let_the_rules_decide() {
+    let result = evaluate(vec![flag("a", &[("b", "on")]), flag("b", &[])]).await;
+    assert_eq!(result.variant.as_deref(), Some("on"));
+    assert!(matches!(
+      result.reason,
+      Reason::RuleMatch { rule_index: 0, .. }
+    ));
+  }
+
+  #[tokio::test]
+  async fn a_direct_cycle_is_an_error() {
+    let result = evaluate(vec![flag("a", &[("b", "on")]), flag("b", &[("a", "on")])]).await;
+    assert_eq!(result.reason, cycle());
+    assert_eq!(result.variant, None);
+  }
+
+  #[tokio::test]
+  async fn a_transitive_cycle_is_an_error() {
+    let flags = vec![
+      flag("a", &[("b", "on")]),
+      flag("b", &[("c", "on")]),
+      flag("c", &[("a", "on")]),
+    ];
+    assert_eq!(evaluate(flags).await.reason, cycle());
+  }
+
+  #[tokio::test]
+  async fn a_missing_prerequisite_serves_the_default_as_not_replicated() {
+    let result = evaluate(vec![flag("a", &[("missing", "on")])]).await;
+    assert_eq!(result.reason, failed("missing", true));
+    assert_eq!(result.variant.as_deref(), Some("on"));
+  }
+
+  #[tokio::test]
+  async fn an_off_prerequisite_fails_even_when_its_default_matches() {
+    let result = evaluate(vec![flag("a", &[("b", "on")]), disabled(flag("b", &[]))]).await;
+    assert_eq!(result.reason, failed("b", false));
+  }
+
+  #[tokio::test]
+  async fn a_prerequisite_serving_another_variant_fails() {
+    let result = evaluate(vec![flag("a", &[("b", "off")]), flag("b", &[])]).await;
+    assert_eq!(result.reason, failed("b", false));
+    assert_eq!(result.variant.as_deref(), Some("on"));
+  }
+
+  #[tokio::test]
+  async fn the_first_failing_prerequisite_is_reported() {
+    let flags = vec![
+      flag("a", &[("b", "on"), ("c", "off"), ("missing", "on")]),
+      flag("b", &[]),
+      flag("c", &[]),
+    ];
+    assert_eq!(evaluate(flags).await.reason, failed("c", false));
+  }
+}
@@ -0,0 +1,39 @@
+use crate::flags::flag::FlagKey;
+use serde::Serialize;
+
+#[derive(PartialEq, Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Reason {
+  /// The flag is disabled, so the default variant was served.
+  Off,
+  /// A prerequisite did not evaluate to the required variant, so the default
+  /// variant was served. `not_replicated` is set when the prerequisite has
+  /// not reached this node yet.
+  PrerequisiteFailed {
+    prerequisite_key: FlagKey,
+    not_replicated: bool,
+  },
+  /// The context key is individually targeted by the flag.
+  TargetMatch,
+  /// A targeting rule matched; `rule_index` is its position in the flag.
+  RuleMatch {
+    rule_index: usize,
+    rule_id: String,
+  },
+  /// The flag is enabled but nothing more specific matched.
+  Fallthrough,
+  /// The flag does not exist in this node's replica.
+  FlagNotFound,
+  Error {
+    error_kind: ErrorKind,
+  },
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorKind {
+  /// The flag refers to a variant it does not define.
+  MalformedFlag,
+  /// The flag's prerequisites lead back to the flag itself.
+  PrerequisiteCycle,
+}
@@ -0,0 +1,30 @@
+use crate::gossip::state::GossipState;
+use crate::node::NodeId;
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Which node answered an evaluation, and how recently its replica heard
+/// from the rest of the mesh.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplicaInfo {
+  pub node_id: NodeId,
+  /// Milliseconds since the Unix epoch at which this node last merged gossip.
+  pub last_gossip_at: Option<u64>,
+  /// Milliseconds since this node last merged gossip.
+  pub gossip_age_ms: Option<u64>,
+}
+
+impl ReplicaInfo {
+  pub fn of(state: &GossipState) -> Self {
+    let now = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .expect("Time went backwards")
+      .as_millis() as u64;
+    let last_gossip_at = state.last_gossip_at();
+    Self {
+      node_id: state.id().clone(),
+      last_gossip_at,
+      gossip_age_ms: last_gossip_at.map(|at| now.saturating_sub(at)),
+    }
+  }
+}
@@ -0,0 +1,10 @@
+pub mod clause;
+pub mod conflict;
+pub mod event;
+pub mod flag;
+pub mod prerequisite;
+pub mod rollout;
+pub mod rule;
+pub mod segment;
+pub mod target;
+pub mod variant;
@@ -0,0 +1,493 @@
+use super::segment::{SegmentKey, SegmentSource};
+use crate::evaluation::context::EvaluationContext;
+use chrono::DateTime;
+use dashmap::DashMap;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::sync::LazyLock;
+
+/// Compiled `matches` patterns, shared by every evaluation on this node.
+static REGEX_CACHE: LazyLock<DashMap<String, Regex>> = LazyLock::new(DashMap::new);
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operator {
+  Equals,
+  In,
+  StartsWith,
+  EndsWith,
+  Contains,
+  Matches,
+  LessThan,
+  LessThanOrEqual,
+  GreaterThan,
+  GreaterThanOrEqual,
+  SemverEqual,
+  SemverLessThan,
+  SemverGreaterThan,
+  Before,
+  After,
+  /// Matches contexts in any of the segments named by the clause's values;
+  /// the attribute is ignored.
+  InSegment,
+}
+
+/// A single test of one context attribute against a list of values. The
+/// clause matches when the attribute matches any of the values; a missing
+/// attribute never matches, even when the clause is negated.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Clause {
+  #[serde(default)]
+  pub attribute: String,
+  pub operator: Operator,
+  pub values: Vec<Value>,
+  #[serde(default)]
+  pub negate: bool,
+}
+
+impl Clause {
+  pub fn matches(&self, context: &EvaluationContext, segments: &dyn SegmentSource) -> bool {
+    if self.operator != Operator::InSegment {
+      return self.matches_attribute(context);
+    }
+    let matched = self
+      .values
+      .iter()
+      .filter_map(|value| value.as_str())
+      .filter_map(|key| segments.segment(&SegmentKey::from(key)))
+      .any(|segment| segment.contains(context));
+    matched != self.negate
+  }
+
+  /// Matches the clause against the context alone, without segment lookups.
+  pub fn matches_attribute(&self, context: &EvaluationContext) -> bool {
+    let Some(attribute) = context.attribute(&self.attribute) else {
+      return false;
+    };
+    let matched = match attribute {
+      Value::Array(items) => items.iter().any(|item| self.matches_value(item)),
+      value => self.matches_value(&value),
+    };
+    matched != self.negate
+  }
+
+  fn matches_value(&self, attribute: &Value) -> bool {
+    self
+      .values
+      .iter()
+      .any(|value| self.operator.apply(attribute, value))
+  }
+
+  pub fn validate(&self) -> eyre::Result<()> {
+    if self.values.is_empty() {
+      eyre::bail!("Clause on {} has no values", self.attribute);
+    }
+    if self.operator == Operator::Equals && self.values.len() != 1 {
+      eyre::bail!("Clause on {} must have exactly one value", self.attribute);
+    }
+    for value in &self.values {
+      self.operator.validate_value(value)?;
+    }
+    Ok(())
+  }
+}
+
+impl Operator {
+  fn apply(self, attribute: &Value, value: &Value) -> bool {
+    match self {
+      Self::Equals | Self::In => attribute == value,
+      Self::StartsWith => strings(attribute, value).is_some_and(|(a, v)| a.starts_with(v)),
+      Self::EndsWith => strings(attribute, value).is_some_and(|(a, v)| a.ends_with(v)),
+      Self::Contains => strings(attribute, value).is_some_and(|(a, v)| a.contains(v)),
+      Self::Matches => strings(attribute, value).is_some_and(|(a, v)| regex_matches(v, a)),
+      Self::LessThan => compare_numbers(attribute, value) == Some(Ordering::Less),
+      Self::LessThanOrEqual => {
+        matches!(
+          compare_numbers(attribute, value),
+          Some(Ordering::Less | Ordering::Equal)
+        )
+      },
+      Self::GreaterThan => compare_numbers(attribute, value) == Some(Ordering::Greater),
+      Self::GreaterThanOrEqual => matches!(
+        compare_numbers(attribute, value),
+        Some(Ordering::Greater | Ordering::Equal)
+      ),
+      Self::SemverEqual => compare_semver(attribute, value) == Some(Ordering::Equal),
+      Self::SemverLessThan => compare_semver(attribute, value) == Some(Ordering::Less),
+      Self::SemverGreaterThan => compare_semver(attribute, value) == Some(Ordering::Greater),
+      Self::Before => compare_dates(attribute, value) == Some(Ordering::Less),
+      Self::After => compare_dates(attribute, value) == Some(Ordering::Greater),
+      Self::InSegment => false,
+    }
+  }
+
+  fn validate_value(self, value: &Value) -> eyre::Result<()> {
+    let valid = match self {
+      Self::Equals | Self::In => true,
+      Self::StartsWith | Self::EndsWith | Self::Contains | Self::InSegment => value.is_string(),
+      Self::Matches => value.as_str().is_some_and(|v| Regex::new(v).is_ok()),
+      Self::LessThan | Self::LessThanOrEqual | Self::GreaterThan | Self::GreaterThanOrEqual => {
+        value.is_number()
+      },
+      Self::SemverEqual | Self::SemverLessThan | Self::SemverGreaterThan => {
+        parse_semver(value).is_some()
+      },
+      Self::Before | Self::After => parse_date(value).is_some(),
+    };
+    if !valid {
+      eyre::bail!("Value {} is not valid for operator {:?}", value, self);
+    }
+    Ok(())
+  }
+}
+
+fn strings<'a>(attribute: &'a Value, value: &'a Value) -> Option<(&'a str, &'a str)> {
+  Some((attribute.as_str()?, value.as_str()?))
+}
+
+fn regex_matches(pattern: &str, haystack: &str) -> bool {
+  if let Some(regex) = REGEX_CACHE.get(pattern) {
+    return regex.is_match(haystack);
+  }
+  match Regex::new(pattern) {
+    Ok(regex) => {
+      let matched = regex.is_match(haystack);
+      REGEX_CACHE.insert(pattern.to_string(), regex);
+      matched
+    },
+    Err(_) => false,
+  }
+}
+
+/// Reads a number, or a string holding one, since attributes passed in a
+/// query string are always strings.
+fn parse_number(value: &Value) -> Option<f64> {
+  match value {
+    Value::Number(number) => number.as_f64(),
+    Value::String(text) => text.trim().parse().ok().filter(|n: &f64| n.is_finite()),
+    _ => None,
+  }
+}
+
+fn compare_numbers(attribute: &Value, value: &Value) -> Option<Ordering> {
+  parse_number(attribute)?.partial_cmp(&parse_number(value)?)
+}
+
+/// Parses a semantic version, tolerating a missing minor or patch component
+/// ("2" and "2.1" are read as "2.0.0" and "2.1.0").
+fn parse_semver(value: &Value) -> Option<semver::Version> {
+  let text = value.as_str()?;
+  if let Ok(version) = semver::Version::parse(text) {
+    return Some(version);
+  }
+  let (core, rest) = match text.find(['-', '+']) {
+    Some(index) => text.split_at(index),
+    None => (text, ""),
+  };
+  let padding = match core.matches('.').count() {
+    0 => ".0.0",
+    1 => ".0",
+    _ => return None,
+  };
+  semver::Version::parse(&format!("{}{}{}", core, padding, rest)).ok()
+}
+
+fn compare_semver(attribute: &Value, value: &Value) -> Option<Ordering> {
+  Some(parse_semver(attribute)?.cmp_precedence(&parse_semver(value)?))
+}
+
+/// Reads a date as either milliseconds since the Unix epoch, as a number or
+/// a string, or an RFC 3339 timestamp, returning milliseconds since the
+/// epoch.
+fn parse_date(value: &Value) -> Option<i64> {
+  match value {
+    Value::Number(number) => number.as_i64(),
+    Value::String(text) => DateTime::parse_from_rfc3339(text)
+      .map(|date| date.timestamp_millis())
+      .ok()
+      .or_else(|| text.trim().parse().ok()),
+    _ => None,
+  }
+}
+
+fn compare_dates(attribute: &Value, value: &Value) -> Option<Ordering> {
+  Some(parse_date(attribute)?.cmp(&parse_date(value)?))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn clause(operator: Operator, values: Vec<Value>, negate: bool) -> Clause {
+    Clause {
+      attribute: "attr".to_string(),
+      operator,
+      values,
+      negate,
+    }
+  }
+
+  fn context(attribute: Option<Value>) -> EvaluationContext {
+    EvaluationContext {
+      key: "user-1".to_string(),
+      attributes: attribute
+        .map(|value| ("attr".to_string(), value))
+        .into_iter()
+        .collect(),
+    }
+  }
+
+  /// Checks each `(operator, attribute, value, matches)` case, with the
+  /// clause as given and negated.
+  fn check(cases: &[(Operator, Value, Value, bool)]) {
+    for (operator, attribute, value, expected) in cases {
+      let context = context(Some(attribute.clone()));
+      for negate in [false, true] {
+        let clause = clause(*operator, vec![value.clone()], negate);
+        assert_eq!(
+          clause.matches_attribute(&context),
+          expected != &negate,
+          "{:?} {} {} (negated: {})",
+          operator,
+          attribute,
+          value,
+          negate
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn compares_numbers() {
+    use Operator::*;
+    check(&[
+      (LessThan, json!(3), json!(5), true),
+      (LessThan, json!(5), json!(5), false),
+      (LessThanOrEqual, json!(5), json!(5), true),
+      (LessThanOrEqual, json!(5.5), json!(5), false),
+      (GreaterThan, json!(5.5), json!(5), true),
+      (GreaterThan, json!(-1), json!(0), false),
+      (GreaterThanOrEqual, json!(5), json!(5.0), true),
+      (GreaterThanOrEqual, json!(4), json!(5), false),
+      // Attributes passed in a query string arrive as strings.
+      (GreaterThan, json!("7"), json!(5), true),
+      (LessThan, json!(" 2.5 "), json!(3), true),
+    ]);
+  }
+
+  #[test]
+  fn numeric_comparisons_with_other_types_never_match() {
+    use Operator::*;
+    for operator in [LessThan, LessThanOrEqual, GreaterThan, GreaterThanOrEqual] {
+      for attribute in [
+        json!("abc"),
+        json!("inf"),
+        json!(true),
+        json!(null),
+        json!({}),
+      ] {
+        let clause = clause(operator, vec![json!(5)], false);
+        assert!(
+          !clause.matches_attribute(&context(Some(attribute.clone()))),
+          "{:?} {}",
+          operator,
+          attribute
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn compares_semantic_versions() {
+    use Operator::*;
+    check(&[
+      (SemverEqual, json!("1.2.3"), json!("1.2.3"), true),
+      (SemverEqual, json!("2"), json!("2.0.0"), true),
+      (SemverEqual, json!("2.1"), json!("2.1.0"), true),
+      (SemverEqual, json!("1.2.3+build.5"), json!("1.2.3"), true),
+      (SemverEqual, json!("1.2.4"), json!("1.2.3"), false),
+      (SemverLessThan, json!("1.2.3-beta"), json!("1.2.3"), true),
+      (SemverLessThan, json!("2.1-rc.1"), json!("2.1"), true),
+      (SemverLessThan, json!("1.10.0"), json!("1.9.0"), false),
+      (SemverGreaterThan, json!("1.10.0"), json!("1.9.0"), true),
+      (
+        SemverGreaterThan,
+        json!("1.0.0-alpha.2"),
+        json!("1.0.0-alpha.10"),
+        false,
+      ),
+    ]);
+  }
+
+  #[test]
+  fn invalid_versions_never_match() {
+    use Operator::*;
+    for operator in [SemverEqual, SemverLessThan, SemverGreaterThan] {
+      for attribute in [json!("banana"), json!("1.2.3.4"), json!(""), json!(1)] {
+        let clause = clause(operator, vec![json!("1.0.0")], false);
+        assert!(
+          !clause.matches_attribute(&context(Some(attribute.clone()))),
+          "{:?} {}",
+          operator,
+          attribute
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn matches_regular_expressions() {
+    use Operator::*;
+    check(&[
+      (
+        Matches,
+        json!("ada@example.com"),
+        json!("^[^@]+@example\\.com$"),
+        true,
+      ),
+      (
+        Matches,
+        json!("ada@example.org"),
+        json!("^[^@]+@example\\.com$"),
+        false,
+      ),
+      (Matches, json!("beta-7"), json!("\\d"), true),
+      // An invalid pattern matches nothing rather than failing evaluation.
+      (Matches, json!("("), json!("("), false),
+      (Matches, json!(5), json!("5"), false),
+    ]);
+  }
+
+  #[test]
+  fn compares_dates() {
+    use Operator::*;
+    check(&[
+      (
+        Before,
+        json!("2024-01-01T00:00:00Z"),
+        json!("2024-06-01T00:00:00Z"),
+        true,
+      ),
+      (
+        Before,
+        json!("2024-06-01T00:00:00Z"),
+        json!("2024-01-01T00:00:00Z"),
+        false,
+      ),
+      (
+        Before,
+        json!("2024-01-01T01:00:00+02:00"),
+        json!("2024-01-01T00:00:00Z"),
+        true,
+      ),
+      (
+        After,
+        json!(1_700_000_000_001i64),
+        json!(1_700_000_000_000i64),
+        true,
+      ),
+      (
+        After,
+        json!("1700000000001"),
+        json!(1_700_000_000_000i64),
+        true,
+      ),
+      (
+        After,
+        json!("2024-01-01T00:00:00Z"),
+        json!(1_700_000_000_000i64),
+        true,
+      ),
+      (
+        After,
+        json!(1_700_000_000_000i64),
+        json!(1_700_000_000_000i64),
+        false,
+      ),
+      (
+        Before,
+        json!("not a date"),
+        json!("2024-01-01T00:00:00Z"),
+        false,
+      ),
+      (After, json!(true), json!(0), false),
+    ]);
+  }
+
+  #[test]
+  fn compares_strings() {
+    use Operator::*;
+    check(&[
+      (StartsWith, json!("beta-7"), json!("beta"), true),
+      (StartsWith, json!("7-beta"), json!("beta"), false),
+      (
+        EndsWith,
+        json!("ada@example.com"),
+        json!("@example.com"),
+        true,
+      ),
+      (Contains, json!("early-beta-7"), json!("beta"), true),
+      (Contains, json!(77), json!("7"), false),
+      (In, json!("pro"), json!("pro"), true),
+      (In, json!(3), json!("3"), false),
+    ]);
+  }
+
+  #[test]
+  fn array_attributes_match_on_any_item() {
+    let clause = clause(Operator::In, vec![json!("admin")], false);
+    assert!(clause.matches_attribute(&context(Some(json!(["staff", "admin"])))));
+    assert!(!clause.matches_attribute(&context(Some(json!(["staff"])))));
+  }
+
+  #[test]
+  fn a_missing_attribute_never_matches_even_when_negated() {
+    for negate in [false, true] {
+      let clause = clause(Operator::In, vec![json!("pro")], negate);
+      assert!(!clause.matches_attribute(&context(None)));
+    }
+  }
+
+  #[test]
+  fn validates_values_for_their_operator() {
+    use Operator::*;
+    let valid = [
+      (Equals, vec![json!({"any": "value"})]),
+      (In, vec![json!("pro"), json!(3)]),
+      (Matches, vec![json!("^beta-\\d+$")]),
+      (LessThan, vec![json!(5)]),
+      (SemverGreaterThan, vec![json!("2.1")]),
+      (
+        Before,
+        vec![json!("2024-01-01T00:00:00Z"), json!(1_700_000_000_000i64)],
+      ),
+      (InSegment, vec![json!("staff")]),
+    ];
+    for (operator, values) in valid {
+      assert!(
+        clause(operator, values, false).validate().is_ok(),
+        "{:?}",
+        operator
+      );
+    }
+    let invalid = [
+      (In, vec![]),
+      (Equals, vec![json!(1), json!(2)]),
+      (Matches, vec![json!("(")]),
+      (Matches, vec![json!(5)]),
+      (StartsWith, vec![json!(5)]),
+      (LessThan, vec![json!("5")]),
+      (SemverEqual, vec![json!("banana")]),
+      (SemverLessThan, vec![json!("1.2.3.4")]),
+      (After, vec![json!("yesterday")]),
+      (InSegment, vec![json!(1)]),
+    ];
+    for (operator, values) in invalid {
+      let clause = clause(operator, values.clone(), false);
+      assert!(clause.validate().is_err(), "{:?} {:?}", operator, values);
+    }
+  }
+}
@@ -0,0 +1,14 @@
+use clap::ValueEnum;
+
+/// How the admin API treats edits to the same flag that were made on
+/// different nodes without either seeing the other.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum ConflictMode {
+  /// The edit with the newest timestamp wins and the others are dropped.
+  #[default]
+  Lww,
+  /// The edits are kept side by side and shown by the admin API, which
+  /// refuses further edits until one resolves them. Evaluation meanwhile
+  /// uses the newest.
+  Siblings,
+}
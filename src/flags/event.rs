@@ -0,0 +1,22 @@
+use super::flag::{Flag, FlagKey};
+use serde::Serialize;
+
+/// A change to the local replica's flags, whether it was written here or
+/// merged from gossip.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FlagEvent {
+  Created { flag: Flag },
+  Updated { flag: Flag },
+  Deleted { key: FlagKey },
+}
+
+impl FlagEvent {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Self::Created { .. } => "created",
+      Self::Updated { .. } => "updated",
+      Self::Deleted { .. } => "deleted",
+    }
+  }
+}
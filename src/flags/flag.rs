@@ -0,0 +1,149 @@
+use super::prerequisite::Prerequisite;
+use super::rule::{Rule, Serve};
+use super::target::Target;
+use super::variant::{FlagKind, Variant};
+use crate::crdts::hlc::Hlc;
+use crate::crdts::last_write_wins::LastWriteWins;
+use crate::node::NodeId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Serialize, Deserialize)]
+pub struct FlagKey(String);
+
+impl FlagKey {
+  pub fn as_str(&self) -> &str {
+    &self.0
+  }
+}
+
+impl From<String> for FlagKey {
+  fn from(key: String) -> Self {
+    Self(key)
+  }
+}
+
+impl From<&str> for FlagKey {
+  fn from(key: &str) -> Self {
+    Self(key.to_string())
+  }
+}
+
+impl std::fmt::Display for FlagKey {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl From<FlagKey> for String {
+  fn from(key: FlagKey) -> Self {
+    key.0
+  }
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Flag {
+  pub key: FlagKey,
+  pub description: String,
+  pub enabled: bool,
+  pub kind: FlagKind,
+  pub variants: Vec<Variant>,
+  pub default_variant: String,
+  /// Flags that must evaluate to a given variant before this flag's rules
+  /// are considered.
+  #[serde(default)]
+  pub prerequisites: Vec<Prerequisite>,
+  /// Individually targeted context keys, checked before the rules.
+  #[serde(default)]
+  pub targets: Vec<Target>,
+  /// Evaluated in order when the flag is enabled; the first match wins.
+  #[serde(default)]
+  pub rules: Vec<Rule>,
+  /// Mixed into rollout bucketing so that flags split users independently.
+  #[serde(default)]
+  pub salt: String,
+  /// Incremented on every write to the flag, wherever it happens.
+  pub version: u64,
+  /// The clock reading at which the flag was last written.
+  pub updated_at: Hlc,
+  pub updated_by: NodeId,
+}
+
+impl Flag {
+  pub fn variant(&self, id: &str) -> Option<&Variant> {
+    self.variants.iter().find(|variant| variant.id == id)
+  }
+
+  pub fn validate(&self) -> eyre::Result<()> {
+    if self.variants.is_empty() {
+      eyre::bail!("Flag {} must have at least one variant", self.key);
+    }
+    let mut seen = HashSet::new();
+    for variant in &self.variants {
+      if !seen.insert(variant.id.as_str()) {
+        eyre::bail!("Flag {} has duplicate variant {}", self.key, variant.id);
+      }
+      if variant.value.kind() != self.kind {
+        eyre::bail!(
+          "Variant {} of flag {} is a {} value, but the flag is {}",
+          variant.id,
+          self.key,
+          variant.value.kind(),
+          self.kind
+        );
+      }
+    }
+    if self.variant(&self.default_variant).is_none() {
+      eyre::bail!(
+        "Flag {} has unknown default variant {}",
+        self.key,
+        self.default_variant
+      );
+    }
+    let mut targeted = HashSet::new();
+    for target in &self.targets {
+      if self.variant(&target.variant).is_none() {
+        eyre::bail!("Target serves unknown variant {}", target.variant);
+      }
+      if let Some(key) = target
+        .keys
+        .iter()
+        .find(|key| !targeted.insert(key.as_str()))
+      {
+        eyre::bail!("Context key {} is targeted more than once", key);
+      }
+    }
+    let mut rule_ids = HashSet::new();
+    for rule in &self.rules {
+      if !rule_ids.insert(rule.id.as_str()) {
+        eyre::bail!("Flag {} has duplicate rule {}", self.key, rule.id);
+      }
+      for clause in &rule.clauses {
+        clause.validate()?;
+      }
+      let served: Vec<&str> = match &rule.serve {
+        Serve::Variant(id) => vec![id],
+        Serve::Rollout(rollout) => {
+          rollout.validate()?;
+          rollout
+            .variants
+            .iter()
+            .map(|v| v.variant.as_str())
+            .collect()
+        },
+      };
+      for id in served {
+        if self.variant(id).is_none() {
+          eyre::bail!("Rule {} serves unknown variant {}", rule.id, id);
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+impl LastWriteWins for Flag {
+  fn timestamp(&self) -> &Hlc {
+    &self.updated_at
+  }
+}
@@ -0,0 +1,124 @@
+use super::flag::{Flag, FlagKey};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Requires another flag to evaluate to `variant` before this one is
+/// evaluated any further.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Prerequisite {
+  pub flag: FlagKey,
+  pub variant: String,
+}
+
+/// Looks for a chain of prerequisites leading from `flag` back to itself,
+/// reading every other flag through `lookup`. Flags that `lookup` cannot find
+/// end their chain, since they may simply not have replicated yet.
+pub fn find_cycle(flag: &Flag, lookup: impl Fn(&FlagKey) -> Option<Flag>) -> Option<Vec<FlagKey>> {
+  let mut visited = HashSet::new();
+  let mut path = vec![flag.key.clone()];
+  visit(flag, &flag.key, &lookup, &mut visited, &mut path)
+}
+
+fn visit(
+  flag: &Flag,
+  target: &FlagKey,
+  lookup: &impl Fn(&FlagKey) -> Option<Flag>,
+  visited: &mut HashSet<FlagKey>,
+  path: &mut Vec<FlagKey>,
+) -> Option<Vec<FlagKey>> {
+  for prerequisite in &flag.prerequisites {
+    path.push(prerequisite.flag.clone());
+    if prerequisite.flag == *target {
+      return Some(path.clone());
+    }
+    if visited.insert(prerequisite.flag.clone())
+      && let Some(next) = lookup(&prerequisite.flag)
+      && let Some(cycle) = visit(&next, target, lookup, visited, path)
+    {
+      return Some(cycle);
+    }
+    path.pop();
+  }
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+  use std::collections::HashMap;
+
+  fn flag(key: &str, prerequisites: &[&str]) -> Flag {
+    let prerequisites: Vec<_> = prerequisites
+      .iter()
+      .map(|flag| json!({"flag": flag, "variant": "on"}))
+      .collect();
+    serde_json::from_value(json!({
+      "key": key,
+      "description": "",
+      "enabled": true,
+      "kind": "boolean",
+      "variants": [{"id": "on", "value": true}],
+      "default_variant": "on",
+      "prerequisites": prerequisites,
+      "version": 1,
+      "updated_at": {"physical_ms": 1, "logical": 0, "node": "a"},
+      "updated_by": "a",
+    }))
+    .unwrap()
+  }
+
+  /// Looks for a cycle from the first of `flags`, with the rest replicated.
+  fn cycle(flags: &[Flag]) -> Option<Vec<String>> {
+    let replicated: HashMap<_, _> = flags
+      .iter()
+      .map(|flag| (flag.key.clone(), flag.clone()))
+      .collect();
+    let cycle = find_cycle(&flags[0], |key| replicated.get(key).cloned())?;
+    Some(cycle.into_iter().map(String::from).collect())
+  }
+
+  #[test]
+  fn finds_a_flag_that_requires_itself() {
+    assert_eq!(cycle(&[flag("a", &["a"])]).unwrap(), ["a", "a"]);
+  }
+
+  #[test]
+  fn finds_a_direct_cycle() {
+    let flags = [flag("a", &["b"]), flag("b", &["a"])];
+    assert_eq!(cycle(&flags).unwrap(), ["a", "b", "a"]);
+  }
+
+  #[test]
+  fn finds_a_transitive_cycle() {
+    let flags = [
+      flag("a", &["x", "b"]),
+      flag("x", &[]),
+      flag("b", &["c"]),
+      flag("c", &["a"]),
+    ];
+    assert_eq!(cycle(&flags).unwrap(), ["a", "b", "c", "a"]);
+  }
+
+  #[test]
+  fn shared_prerequisites_are_not_a_cycle() {
+    let flags = [
+      flag("a", &["b", "c"]),
+      flag("b", &["d"]),
+      flag("c", &["d"]),
+      flag("d", &[]),
+    ];
+    assert!(cycle(&flags).is_none());
+  }
+
+  #[test]
+  fn missing_prerequisites_end_the_chain() {
+    assert!(cycle(&[flag("a", &["missing"])]).is_none());
+  }
+
+  #[test]
+  fn ignores_cycles_that_do_not_lead_back_to_the_flag() {
+    let flags = [flag("a", &["b"]), flag("b", &["c"]), flag("c", &["b"])];
+    assert!(cycle(&flags).is_none());
+  }
+}
@@ -0,0 +1,162 @@
+use crate::evaluation::context::EvaluationContext;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// Rollout weights are expressed in thousandths of a percent.
+pub const BUCKET_COUNT: u32 = 100_000;
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct WeightedVariant {
+  pub variant: String,
+  pub weight: u32,
+}
+
+/// Splits contexts between variants by hashing them into one of
+/// `BUCKET_COUNT` buckets. Buckets are handed out to the variants in order, so
+/// growing the first variant from 10% to 20% keeps everyone who already had it
+/// and only moves contexts from the next variant over.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Rollout {
+  /// The attribute to bucket on; the context key when omitted.
+  #[serde(default)]
+  pub bucket_by: Option<String>,
+  pub variants: Vec<WeightedVariant>,
+}
+
+impl Rollout {
+  pub fn variant_for(&self, flag_key: &str, salt: &str, context: &EvaluationContext) -> &str {
+    let attribute = self.bucket_by.as_deref().unwrap_or("key");
+    let bucket = context
+      .attribute(attribute)
+      .and_then(|value| bucket_value(&value))
+      .map_or(0, |value| bucket(flag_key, salt, &value));
+    let mut upper = 0;
+    for weighted in &self.variants {
+      upper += weighted.weight;
+      if bucket < upper {
+        return &weighted.variant;
+      }
+    }
+    // Unreachable for a validated rollout, whose weights cover every bucket.
+    &self.variants[self.variants.len() - 1].variant
+  }
+
+  pub fn validate(&self) -> eyre::Result<()> {
+    let total: u64 = self.variants.iter().map(|v| u64::from(v.weight)).sum();
+    if total != u64::from(BUCKET_COUNT) {
+      eyre::bail!("Rollout weights add up to {}, not {}", total, BUCKET_COUNT);
+    }
+    Ok(())
+  }
+}
+
+/// Only strings and integers are stable enough to bucket on.
+fn bucket_value(value: &Value) -> Option<String> {
+  match value {
+    Value::String(text) => Some(text.clone()),
+    Value::Number(number) if number.is_i64() || number.is_u64() => Some(number.to_string()),
+    _ => None,
+  }
+}
+
+/// Maps a context to a bucket in `0..BUCKET_COUNT`. This runs independently on
+/// every node, so it only depends on SHA-256 and a big-endian read of its
+/// output, never on the platform's hasher or byte order.
+pub fn bucket(flag_key: &str, salt: &str, value: &str) -> u32 {
+  let digest = Sha256::new()
+    .chain_update(flag_key.as_bytes())
+    .chain_update(b".")
+    .chain_update(salt.as_bytes())
+    .chain_update(b".")
+    .chain_update(value.as_bytes())
+    .finalize();
+  let mut prefix = [0u8; 8];
+  prefix.copy_from_slice(&digest[..8]);
+  (u64::from_be_bytes(prefix) % u64::from(BUCKET_COUNT)) as u32
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn rollout(weights: &[(&str, u32)]) -> Rollout {
+    Rollout {
+      bucket_by: None,
+      variants: weights
+        .iter()
+        .map(|(variant, weight)| WeightedVariant {
+          variant: variant.to_string(),
+          weight: *weight,
+        })
+        .collect(),
+    }
+  }
+
+  fn context(key: &str) -> EvaluationContext {
+    EvaluationContext {
+      key: key.to_string(),
+      ..Default::default()
+    }
+  }
+
+  /// Every node, on every platform and version, has to agree on these.
+  #[test]
+  fn buckets_are_pinned() {
+    assert_eq!(bucket("new-checkout", "salt-1", "user-1"), 36060);
+    assert_eq!(bucket("new-checkout", "salt-1", "user-2"), 21713);
+    assert_eq!(bucket("new-checkout", "salt-2", "user-1"), 7704);
+    assert_eq!(bucket("dark-mode", "", "42"), 72908);
+  }
+
+  #[test]
+  fn a_variant_covers_buckets_below_its_upper_bound() {
+    // user-1 lands in bucket 36060.
+    let user = context("user-1");
+    let below = rollout(&[("on", 36060), ("off", BUCKET_COUNT - 36060)]);
+    assert_eq!(below.variant_for("new-checkout", "salt-1", &user), "off");
+    let above = rollout(&[("on", 36061), ("off", BUCKET_COUNT - 36061)]);
+    assert_eq!(above.variant_for("new-checkout", "salt-1", &user), "on");
+  }
+
+  #[test]
+  fn zero_and_full_weights() {
+    let user = context("user-1");
+    let all = rollout(&[("on", BUCKET_COUNT), ("off", 0)]);
+    assert_eq!(all.variant_for("new-checkout", "salt-1", &user), "on");
+    let none = rollout(&[("on", 0), ("off", BUCKET_COUNT)]);
+    assert_eq!(none.variant_for("new-checkout", "salt-1", &user), "off");
+  }
+
+  #[test]
+  fn integer_attributes_bucket_like_their_digits() {
+    let mut user = context("user-1");
+    user
+      .attributes
+      .insert("account".to_string(), Value::from(42));
+    let rollout = Rollout {
+      bucket_by: Some("account".to_string()),
+      ..rollout(&[("on", 72909), ("off", BUCKET_COUNT - 72909)])
+    };
+    assert_eq!(rollout.variant_for("dark-mode", "", &user), "on");
+  }
+
+  #[test]
+  fn weights_have_to_cover_every_bucket() {
+    assert!(
+      rollout(&[("on", 50_000), ("off", 50_000)])
+        .validate()
+        .is_ok()
+    );
+    assert!(
+      rollout(&[("on", 50_000), ("off", 49_999)])
+        .validate()
+        .is_err()
+    );
+    assert!(
+      rollout(&[("on", BUCKET_COUNT), ("off", 1)])
+        .validate()
+        .is_err()
+    );
+  }
+}
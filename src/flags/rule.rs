@@ -0,0 +1,31 @@
+use super::clause::Clause;
+use super::rollout::Rollout;
+use super::segment::SegmentSource;
+use crate::evaluation::context::EvaluationContext;
+use serde::{Deserialize, Serialize};
+
+/// What a matching rule hands back to the caller.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Serve {
+  Variant(String),
+  Rollout(Rollout),
+}
+
+/// A targeting rule matches when every one of its clauses does.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+  pub id: String,
+  #[serde(default)]
+  pub clauses: Vec<Clause>,
+  pub serve: Serve,
+}
+
+impl Rule {
+  pub fn matches(&self, context: &EvaluationContext, segments: &dyn SegmentSource) -> bool {
+    self
+      .clauses
+      .iter()
+      .all(|clause| clause.matches(context, segments))
+  }
+}
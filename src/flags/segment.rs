@@ -0,0 +1,97 @@
+use super::clause::{Clause, Operator};
+use crate::crdts::hlc::Hlc;
+use crate::crdts::last_write_wins::LastWriteWins;
+use crate::evaluation::context::EvaluationContext;
+use crate::node::NodeId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Serialize, Deserialize)]
+pub struct SegmentKey(String);
+
+impl From<String> for SegmentKey {
+  fn from(key: String) -> Self {
+    Self(key)
+  }
+}
+
+impl From<&str> for SegmentKey {
+  fn from(key: &str) -> Self {
+    Self(key.to_string())
+  }
+}
+
+impl std::fmt::Display for SegmentKey {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+/// Anything that can look segments up by key while a flag is evaluated.
+pub trait SegmentSource {
+  fn segment(&self, key: &SegmentKey) -> Option<Segment>;
+}
+
+/// A segment rule matches when every one of its clauses does. Segment rules
+/// cannot refer to other segments.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct SegmentRule {
+  pub id: String,
+  #[serde(default)]
+  pub clauses: Vec<Clause>,
+}
+
+/// A named group of contexts that flag rules can target with `in_segment`.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Segment {
+  pub key: SegmentKey,
+  pub description: String,
+  /// Context keys that are always in the segment, unless also excluded.
+  pub included: BTreeSet<String>,
+  /// Context keys that are never in the segment.
+  pub excluded: BTreeSet<String>,
+  pub rules: Vec<SegmentRule>,
+  pub version: u64,
+  /// The clock reading at which the segment was last written.
+  pub updated_at: Hlc,
+  pub updated_by: NodeId,
+}
+
+impl Segment {
+  pub fn contains(&self, context: &EvaluationContext) -> bool {
+    if self.excluded.contains(&context.key) {
+      return false;
+    }
+    if self.included.contains(&context.key) {
+      return true;
+    }
+    self.rules.iter().any(|rule| {
+      rule
+        .clauses
+        .iter()
+        .all(|clause| clause.matches_attribute(context))
+    })
+  }
+
+  pub fn validate(&self) -> eyre::Result<()> {
+    let mut rule_ids = HashSet::new();
+    for rule in &self.rules {
+      if !rule_ids.insert(rule.id.as_str()) {
+        eyre::bail!("Segment {} has duplicate rule {}", self.key, rule.id);
+      }
+      for clause in &rule.clauses {
+        if clause.operator == Operator::InSegment {
+          eyre::bail!("Segment rule {} cannot refer to other segments", rule.id);
+        }
+        clause.validate()?;
+      }
+    }
+    Ok(())
+  }
+}
+
+impl LastWriteWins for Segment {
+  fn timestamp(&self) -> &Hlc {
+    &self.updated_at
+  }
+}
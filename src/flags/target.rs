@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// Serves `variant` to the listed context keys, ahead of any rule.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Target {
+  pub variant: String,
+  pub keys: BTreeSet<String>,
+}
@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+
+/// The type every variant of a flag must share.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlagKind {
+  Boolean,
+  String,
+  Number,
+  Json,
+}
+
+impl std::fmt::Display for FlagKind {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let name = match self {
+      Self::Boolean => "boolean",
+      Self::String => "string",
+      Self::Number => "number",
+      Self::Json => "json",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+/// A variant's value. JSON values must be objects; arrays and nulls are
+/// rejected when the variant is deserialized.
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum VariantValue {
+  Boolean(bool),
+  Number(Number),
+  String(String),
+  Json(Map<String, Value>),
+}
+
+impl VariantValue {
+  pub fn kind(&self) -> FlagKind {
+    match self {
+      Self::Boolean(_) => FlagKind::Boolean,
+      Self::Number(_) => FlagKind::Number,
+      Self::String(_) => FlagKind::String,
+      Self::Json(_) => FlagKind::Json,
+    }
+  }
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Variant {
+  pub id: String,
+  pub value: VariantValue,
+}
@@ -1,3 +1,10 @@
+pub mod anti_entropy;
+pub mod auth;
+pub mod codec;
+pub mod leave;
 pub mod listener;
 pub mod state;
+pub mod swim;
+pub mod tls;
+pub mod tombstones;
 pub mod whisperer;
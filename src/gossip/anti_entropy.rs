@@ -0,0 +1,126 @@
+use super::state::{GossipState, SyncRequest, SyncResponse, TreeRequest};
+use super::whisperer::{GOSSIP_BATCH, select_gossip_targets, send_gossip};
+use crate::crdts::merkle::{DEPTH, Maps};
+use crate::node::NodeId;
+use crate::shutdown::container::ShutdownContainer;
+use reqwest::Client;
+use std::time::Duration;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, instrument, trace};
+
+/// How often a node that has synced once compares replicas with a peer.
+const SYNC_INTERVAL: Duration = Duration::from_secs(15);
+/// How often a node that has not synced yet looks for a peer to pull from.
+const BOOTSTRAP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Walks down the Merkle trees of `id` at `target_addr` and the local
+/// replica, one level per round trip, and returns the leaf buckets in which
+/// they differ.
+#[instrument]
+pub async fn differing_buckets(
+  client: &Client,
+  app: &GossipState,
+  id: &NodeId,
+  target_addr: &str,
+) -> eyre::Result<Maps<Vec<usize>>> {
+  let trees = app.trees();
+  let mut differing = Maps::all(vec![0]);
+  for level in 0..=DEPTH {
+    let request = TreeRequest {
+      from: app.id().clone(),
+      cluster: app.cluster().to_string(),
+      level,
+      parents: differing,
+    };
+    let url = app.auth().url(target_addr, "/gossip/tree");
+    let reply = app
+      .auth()
+      .send(client.post(url), id, app.wire_for(id), &request)
+      .await?;
+    let theirs: Maps<Vec<(usize, u64)>> = reply.decode()?;
+    differing = trees.zip(&theirs, |tree, theirs| tree.differing(level, theirs));
+  }
+  Ok(differing)
+}
+
+/// Finds where the replica differs from that of `id` at `target_addr`,
+/// exchanges digests of just those buckets, merges what it sends back, and
+/// pushes it whatever it asked for, a gossip batch at a time.
+#[instrument]
+pub async fn sync_with(
+  client: &Client,
+  app: &GossipState,
+  id: &NodeId,
+  target_addr: &str,
+) -> eyre::Result<()> {
+  let scope = differing_buckets(client, app, id, target_addr).await?;
+  if scope.iter().all(Vec::is_empty) {
+    trace!("Replica agrees with {}", target_addr);
+    return Ok(());
+  }
+  let request = SyncRequest {
+    from: app.id().clone(),
+    cluster: app.cluster().to_string(),
+    digests: app.digests(Some(&scope)),
+    scope: Some(scope),
+  };
+  let url = app.auth().url(target_addr, "/gossip/sync");
+  let reply = app
+    .auth()
+    .send(client.post(url), id, app.wire_for(id), &request)
+    .await?;
+  let response: SyncResponse = reply.decode()?;
+  let payload = &response.payload;
+  app.check_cluster(&payload.from, &payload.cluster)?;
+  let received =
+    response.payload.diffs.len() + response.payload.flags.len() + response.payload.segments.len();
+  app.merge_payload(response.payload).await;
+  for wanted in response.wanted.chunks(GOSSIP_BATCH) {
+    let payload = app.payload_for(&wanted);
+    send_gossip(client, app, id, target_addr, &payload).await?;
+  }
+  trace!("Synced with {}: received {} entries", target_addr, received);
+  Ok(())
+}
+
+/// Periodic push-pull anti-entropy with one random peer. Until the first
+/// exchange succeeds the node polls for a peer every second, so a node that
+/// has just joined pulls the whole replica as soon as discovery finds one.
+#[instrument]
+pub async fn anti_entropy(
+  container: &ShutdownContainer,
+  cancel: CancellationToken,
+) -> eyre::Result<()> {
+  let app = container.gossip_state.clone();
+  let client = &container.http_client;
+  let mut bootstrapped = false;
+  info!("Starting anti-entropy loop...");
+  loop {
+    let delay = if bootstrapped {
+      SYNC_INTERVAL
+    } else {
+      BOOTSTRAP_INTERVAL
+    };
+    tokio::select! {
+      biased;
+      _ = cancel.cancelled() => {
+        debug!("Anti-entropy loop received shutdown");
+        break Ok(());
+      }
+      _ = sleep(delay) => {
+        let Some((id, address)) = select_gossip_targets(&app, 1).pop() else {
+          continue;
+        };
+        match sync_with(client, &app, &id, &address).await {
+          Ok(()) if !bootstrapped => {
+            info!("Bootstrapped replica from {}", id);
+            bootstrapped = true;
+          },
+          Ok(()) => {},
+          Err(error) => debug!("Failed to sync with {}: {}", id, error),
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,707 @@
+use super::codec::{MAX_BODY, Wire};
+use super::tls::{MeshTls, Peer, PeerCert};
+use crate::node::NodeId;
+use axum::body::{Body, Bytes, to_bytes};
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use hmac::{Hmac, Mac};
+use reqwest::RequestBuilder;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use sha2::Sha256;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+const CHALLENGE_HEADER: &str = "x-flags-challenge";
+const SIGNATURE_HEADER: &str = "x-flags-signature";
+/// Set apart the signatures of requests and replies, so that one can't be
+/// passed off as the other.
+const REQUEST: &str = "request";
+const REPLY: &str = "reply";
+/// How long a challenge stays good for, by the clock of the node that issued
+/// it. Holders give up on theirs a little earlier, so as not to spend one
+/// that is about to lapse.
+const CHALLENGE_TTL: Duration = Duration::from_secs(60);
+const CHALLENGE_MARGIN: Duration = Duration::from_secs(5);
+/// The most challenges a node keeps outstanding for any one address; past
+/// this, that address's oldest ones are dropped. Challenges are kept apart
+/// by address, so that flooding a node with requests for them only pushes
+/// out the flooder's own.
+const MAX_CHALLENGES_PER_ADDRESS: usize = 64;
+/// The most addresses a node keeps challenges for at once. Past this, new
+/// addresses are refused challenges until older ones' have lapsed, rather
+/// than pushing out those of peers already gossiping.
+const MAX_CHALLENGE_ADDRESSES: usize = 1024;
+/// The most unspent challenges from each peer kept for later requests.
+const MAX_HELD_CHALLENGES: usize = 16;
+
+type HmacSha256 = Hmac<Sha256>;
+/// Challenges from one peer, with when they were received.
+type Held = VecDeque<(String, Instant)>;
+/// Challenges issued to one address, with when they were issued.
+type Issued = HashMap<String, Instant>;
+
+/// Shared secrets for gossip: the first signs outgoing requests, and any of
+/// them verifies incoming ones, so keys can be rotated one node at a time.
+#[derive(Clone, Default)]
+pub struct GossipKeys(Vec<Vec<u8>>);
+
+impl GossipKeys {
+  /// Reads the keys given directly, then those in `file`, one per line.
+  pub fn load(keys: &[String], file: Option<&Path>) -> eyre::Result<Self> {
+    let mut all: Vec<String> = keys.to_vec();
+    if let Some(file) = file {
+      let contents = std::fs::read_to_string(file)
+        .map_err(|error| eyre::eyre!("Failed to read {}: {}", file.display(), error))?;
+      all.extend(contents.lines().map(String::from));
+    }
+    let keys = all
+      .iter()
+      .map(|key| key.trim())
+      .filter(|key| !key.is_empty())
+      .map(|key| key.as_bytes().to_vec())
+      .collect();
+    Ok(Self(keys))
+  }
+}
+
+impl std::fmt::Debug for GossipKeys {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "GossipKeys({} keys)", self.0.len())
+  }
+}
+
+/// Why a request, or a reply, was refused.
+#[derive(Debug, Clone, Copy)]
+enum Rejection {
+  Unsigned,
+  BadSignature,
+  Replayed,
+}
+
+impl std::fmt::Display for Rejection {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Rejection::Unsigned => write!(f, "not signed"),
+      Rejection::BadSignature => write!(f, "signature matches no key"),
+      Rejection::Replayed => write!(f, "challenge unknown, expired or already used"),
+    }
+  }
+}
+
+/// How many gossip requests and replies have been refused, by reason.
+#[derive(Debug, Default)]
+struct Rejections {
+  unsigned: AtomicU64,
+  bad_signature: AtomicU64,
+  replayed: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RejectionCounts {
+  pub unsigned: u64,
+  pub bad_signature: u64,
+  pub replayed: u64,
+}
+
+/// Signs outgoing gossip requests and verifies incoming ones with HMAC-SHA256
+/// over a challenge, the path and the body, and likewise the replies. Each
+/// challenge is issued by the receiving node and accepted once, which keeps
+/// requests from being replayed without the nodes' clocks having to agree;
+/// every reply carries a fresh one for the next request. A challenge is only
+/// good for requests from the address it was issued to. Without keys,
+/// requests go out unsigned and everything is accepted. With mesh TLS,
+/// peers are reached over HTTPS and have to prove their node ID with their
+/// certificate.
+#[derive(Clone, Debug)]
+pub struct GossipAuth {
+  keys: GossipKeys,
+  tls: Option<MeshTls>,
+  /// Challenges this node issued and has not seen used yet, by the address
+  /// they were issued to.
+  issued: Arc<Mutex<HashMap<IpAddr, Issued>>>,
+  /// Challenges peers issued to this node, by peer, oldest first.
+  held: Arc<Mutex<HashMap<NodeId, Held>>>,
+  rejections: Arc<Rejections>,
+}
+
+impl GossipAuth {
+  pub fn new(keys: GossipKeys, tls: Option<MeshTls>) -> Self {
+    Self {
+      keys,
+      tls,
+      issued: Arc::new(Mutex::new(HashMap::new())),
+      held: Arc::new(Mutex::new(HashMap::new())),
+      rejections: Arc::new(Rejections::default()),
+    }
+  }
+
+  pub fn is_enabled(&self) -> bool {
+    !self.keys.0.is_empty()
+  }
+
+  pub fn key_count(&self) -> usize {
+    self.keys.0.len()
+  }
+
+  pub fn tls(&self) -> Option<&MeshTls> {
+    self.tls.as_ref()
+  }
+
+  /// The URL of `path` on the node at `address`.
+  pub fn url(&self, address: &str, path: &str) -> String {
+    let scheme = if self.tls.is_some() { "https" } else { "http" };
+    format!("{}://{}{}", scheme, address, path)
+  }
+
+  /// Refuses a response from anyone but `id`. Only meaningful with mesh TLS;
+  /// plain HTTP responses are taken at their word.
+  pub fn check_peer(&self, response: &reqwest::Response, id: &NodeId) -> eyre::Result<()> {
+    if self.tls.is_none() {
+      return Ok(());
+    }
+    PeerCert::of_response(response).check(id)
+  }
+
+  /// Sends `body` to `id` in the `wire` format, signed with the first key
+  /// over a challenge from `id`, and returns its reply once the reply is
+  /// known to come from a holder of one of the keys and to answer this very
+  /// request. Both signatures cover the body as sent, compressed or not.
+  pub async fn send<T: Serialize>(
+    &self,
+    request: RequestBuilder,
+    id: &NodeId,
+    wire: Wire,
+    body: &T,
+  ) -> eyre::Result<Reply> {
+    let (client, request) = request.build_split();
+    let mut request = request?;
+    let path = request.url().path().to_string();
+    let body = wire.encode(body)?;
+    let challenge = match self.keys.0.first() {
+      Some(key) => {
+        let challenge = match self.take_challenge(id) {
+          Some(challenge) => challenge,
+          None => self.fetch_challenge(&client, &request, id).await?,
+        };
+        let signature = mac(key, REQUEST, &challenge, &path, &body.bytes)
+          .finalize()
+          .into_bytes();
+        let headers = request.headers_mut();
+        headers.insert(CHALLENGE_HEADER, HeaderValue::from_str(&challenge)?);
+        headers.insert(
+          SIGNATURE_HEADER,
+          HeaderValue::from_str(&hex::encode(signature))?,
+        );
+        Some(challenge)
+      },
+      None => None,
+    };
+    body.apply(&mut request);
+    let response = client.execute(request).await?.error_for_status()?;
+    self.check_peer(&response, id)?;
+    let headers = response.headers().clone();
+    let body = response.bytes().await?;
+    if let Some(challenge) = challenge {
+      if let Err(rejection) = self.verify_reply(&headers, &challenge, &path, &body) {
+        self.count(rejection);
+        warn!(
+          "Rejected gossip reply from {} to {}: {}",
+          id, path, rejection
+        );
+        eyre::bail!("Reply from {} to {} {}", id, path, rejection);
+      }
+      if let Some(next) = headers
+        .get(CHALLENGE_HEADER)
+        .and_then(|value| value.to_str().ok())
+      {
+        self.hold_challenge(id, next.to_string());
+      }
+    }
+    Ok(Reply { headers, body })
+  }
+
+  /// Asks the node `request` is addressed to for a challenge, with the same
+  /// timeout as the request itself.
+  async fn fetch_challenge(
+    &self,
+    client: &reqwest::Client,
+    request: &reqwest::Request,
+    id: &NodeId,
+  ) -> eyre::Result<String> {
+    let mut url = request.url().clone();
+    url.set_path("/gossip/challenge");
+    url.set_query(None);
+    let mut fetch = client.get(url);
+    if let Some(timeout) = request.timeout() {
+      fetch = fetch.timeout(*timeout);
+    }
+    let response = fetch.send().await?.error_for_status()?;
+    self.check_peer(&response, id)?;
+    Ok(response.text().await?.trim().to_string())
+  }
+
+  /// The newest challenge held from `id` that is still good, if any.
+  fn take_challenge(&self, id: &NodeId) -> Option<String> {
+    let mut held = self.held.lock().unwrap();
+    let challenges = held.get_mut(id)?;
+    while let Some((challenge, received)) = challenges.pop_back() {
+      if received.elapsed() < CHALLENGE_TTL - CHALLENGE_MARGIN {
+        return Some(challenge);
+      }
+    }
+    None
+  }
+
+  fn hold_challenge(&self, id: &NodeId, challenge: String) {
+    let mut held = self.held.lock().unwrap();
+    let challenges = held.entry(id.clone()).or_default();
+    challenges.push_back((challenge, Instant::now()));
+    if challenges.len() > MAX_HELD_CHALLENGES {
+      challenges.pop_front();
+    }
+  }
+
+  /// Issues a challenge that one request to this node from `address` may be
+  /// signed over, unless too many other addresses already hold challenges.
+  pub fn issue_challenge(&self, address: IpAddr) -> Option<String> {
+    let mut issued = self.issued.lock().unwrap();
+    issued.retain(|_, challenges| {
+      challenges.retain(|_, at| at.elapsed() < CHALLENGE_TTL);
+      !challenges.is_empty()
+    });
+    if !issued.contains_key(&address) && issued.len() >= MAX_CHALLENGE_ADDRESSES {
+      return None;
+    }
+    let challenges = issued.entry(address).or_default();
+    if challenges.len() >= MAX_CHALLENGES_PER_ADDRESS
+      && let Some(oldest) = challenges
+        .iter()
+        .min_by_key(|(_, at)| **at)
+        .map(|(challenge, _)| challenge.clone())
+    {
+      challenges.remove(&oldest);
+    }
+    let challenge = format!("{:032x}", rand::random::<u128>());
+    challenges.insert(challenge.clone(), Instant::now());
+    Some(challenge)
+  }
+
+  /// Checks that a reply to the request signed over `challenge` is signed
+  /// too.
+  fn verify_reply(
+    &self,
+    headers: &HeaderMap,
+    challenge: &str,
+    path: &str,
+    body: &[u8],
+  ) -> Result<(), Rejection> {
+    let signature = headers
+      .get(SIGNATURE_HEADER)
+      .and_then(|value| value.to_str().ok())
+      .ok_or(Rejection::Unsigned)?;
+    let signature = hex::decode(signature).map_err(|_| Rejection::BadSignature)?;
+    let signed = self.keys.0.iter().any(|key| {
+      mac(key, REPLY, challenge, path, body)
+        .verify_slice(&signature)
+        .is_ok()
+    });
+    if !signed {
+      return Err(Rejection::BadSignature);
+    }
+    Ok(())
+  }
+
+  /// Signs a reply to the request that carried `challenge`.
+  fn sign_reply(&self, challenge: &str, path: &str, body: &[u8]) -> Option<HeaderValue> {
+    let key = self.keys.0.first()?;
+    let signature = mac(key, REPLY, challenge, path, body)
+      .finalize()
+      .into_bytes();
+    HeaderValue::from_str(&hex::encode(signature)).ok()
+  }
+
+  fn verify(
+    &self,
+    headers: &HeaderMap,
+    address: IpAddr,
+    path: &str,
+    body: &[u8],
+  ) -> Result<(), Rejection> {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let (Some(challenge), Some(signature)) = (header(CHALLENGE_HEADER), header(SIGNATURE_HEADER))
+    else {
+      return Err(Rejection::Unsigned);
+    };
+    let signature = hex::decode(signature).map_err(|_| Rejection::BadSignature)?;
+    let signed = self.keys.0.iter().any(|key| {
+      mac(key, REQUEST, challenge, path, body)
+        .verify_slice(&signature)
+        .is_ok()
+    });
+    if !signed {
+      return Err(Rejection::BadSignature);
+    }
+    let issued = self
+      .issued
+      .lock()
+      .unwrap()
+      .get_mut(&address)
+      .and_then(|challenges| challenges.remove(challenge));
+    match issued {
+      Some(at) if at.elapsed() < CHALLENGE_TTL => Ok(()),
+      _ => Err(Rejection::Replayed),
+    }
+  }
+
+  fn count(&self, rejection: Rejection) {
+    let counter = match rejection {
+      Rejection::Unsigned => &self.rejections.unsigned,
+      Rejection::BadSignature => &self.rejections.bad_signature,
+      Rejection::Replayed => &self.rejections.replayed,
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub fn rejections(&self) -> RejectionCounts {
+    RejectionCounts {
+      unsigned: self.rejections.unsigned.load(Ordering::Relaxed),
+      bad_signature: self.rejections.bad_signature.load(Ordering::Relaxed),
+      replayed: self.rejections.replayed.load(Ordering::Relaxed),
+    }
+  }
+}
+
+fn mac(key: &[u8], kind: &str, challenge: &str, path: &str, body: &[u8]) -> HmacSha256 {
+  let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+  for part in [kind.as_bytes(), challenge.as_bytes(), path.as_bytes()] {
+    mac.update(part);
+    mac.update(b"\n");
+  }
+  mac.update(body);
+  mac
+}
+
+/// A peer's reply to a gossip request, read in full so that its signature
+/// could be checked.
+#[derive(Debug)]
+pub struct Reply {
+  headers: HeaderMap,
+  body: Bytes,
+}
+
+impl Reply {
+  /// Decodes the reply in whatever wire format it was sent in.
+  pub fn decode<T: DeserializeOwned>(&self) -> eyre::Result<T> {
+    let wire = Wire::of_content(&self.headers)
+      .ok_or_else(|| eyre::eyre!("Reply is in an unsupported format"))?;
+    wire.decode(&self.body)
+  }
+}
+
+/// Refuses gossip requests that aren't signed with a known key over a
+/// challenge this node issued to their address and has not seen used,
+/// before they reach their handlers, and signs the replies to those it lets
+/// through.
+pub async fn verify_request(
+  State(auth): State<GossipAuth>,
+  ConnectInfo(peer): ConnectInfo<Peer>,
+  request: Request,
+  next: Next,
+) -> Response {
+  if !auth.is_enabled() {
+    return next.run(request).await;
+  }
+  let (parts, body) = request.into_parts();
+  let Ok(body) = to_bytes(body, MAX_BODY).await else {
+    return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+  };
+  let address = peer.address.ip();
+  if let Err(rejection) = auth.verify(&parts.headers, address, parts.uri.path(), &body) {
+    auth.count(rejection);
+    warn!(
+      "Rejected gossip request to {}: {}",
+      parts.uri.path(),
+      rejection
+    );
+    return StatusCode::UNAUTHORIZED.into_response();
+  }
+  let challenge = parts
+    .headers
+    .get(CHALLENGE_HEADER)
+    .and_then(|value| value.to_str().ok())
+    .unwrap_or_default()
+    .to_string();
+  let path = parts.uri.path().to_string();
+  let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+  let (mut parts, body) = response.into_parts();
+  let Ok(body) = to_bytes(body, MAX_BODY).await else {
+    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+  };
+  if let Some(signature) = auth.sign_reply(&challenge, &path, &body) {
+    parts.headers.insert(SIGNATURE_HEADER, signature);
+  }
+  if let Some(next) = auth.issue_challenge(address)
+    && let Ok(next) = HeaderValue::from_str(&next)
+  {
+    parts.headers.insert(CHALLENGE_HEADER, next);
+  }
+  Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::gossip::tls::MeshListener;
+  use axum::middleware;
+  use axum::routing::{get, post};
+  use axum::{Json, Router};
+  use serde_json::{Value, json};
+  use std::net::{Ipv4Addr, SocketAddr};
+  use tokio::net::TcpListener;
+
+  const PATH: &str = "/gossip";
+  const BODY: &[u8] = b"{\"from\":\"a\"}";
+
+  fn auth(keys: &[&str]) -> GossipAuth {
+    let keys: Vec<_> = keys.iter().map(|key| key.to_string()).collect();
+    GossipAuth::new(GossipKeys::load(&keys, None).unwrap(), None)
+  }
+
+  fn address(last: u8) -> IpAddr {
+    IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+  }
+
+  /// The headers of a request signed with `key` over `challenge`.
+  fn signed(key: &str, challenge: &str, body: &[u8]) -> HeaderMap {
+    let signature = mac(key.as_bytes(), REQUEST, challenge, PATH, body)
+      .finalize()
+      .into_bytes();
+    let mut headers = HeaderMap::new();
+    headers.insert(CHALLENGE_HEADER, challenge.parse().unwrap());
+    headers.insert(SIGNATURE_HEADER, hex::encode(signature).parse().unwrap());
+    headers
+  }
+
+  fn verify(auth: &GossipAuth, headers: &HeaderMap, from: IpAddr) -> Result<(), Rejection> {
+    auth.verify(headers, from, PATH, BODY)
+  }
+
+  #[test]
+  fn accepts_a_request_signed_over_a_fresh_challenge() {
+    let auth = auth(&["secret"]);
+    let challenge = auth.issue_challenge(address(1)).unwrap();
+    assert!(verify(&auth, &signed("secret", &challenge, BODY), address(1)).is_ok());
+  }
+
+  #[test]
+  fn refuses_unsigned_and_tampered_requests() {
+    let auth = auth(&["secret"]);
+    let challenge = auth.issue_challenge(address(1)).unwrap();
+    assert!(matches!(
+      verify(&auth, &HeaderMap::new(), address(1)),
+      Err(Rejection::Unsigned)
+    ));
+    let tampered = signed("secret", &challenge, b"{\"from\":\"b\"}");
+    assert!(matches!(
+      verify(&auth, &tampered, address(1)),
+      Err(Rejection::BadSignature)
+    ));
+    let other_key = signed("other", &challenge, BODY);
+    assert!(matches!(
+      verify(&auth, &other_key, address(1)),
+      Err(Rejection::BadSignature)
+    ));
+  }
+
+  #[test]
+  fn accepts_each_challenge_once() {
+    let auth = auth(&["secret"]);
+    let challenge = auth.issue_challenge(address(1)).unwrap();
+    let headers = signed("secret", &challenge, BODY);
+    assert!(verify(&auth, &headers, address(1)).is_ok());
+    assert!(matches!(
+      verify(&auth, &headers, address(1)),
+      Err(Rejection::Replayed)
+    ));
+  }
+
+  #[test]
+  fn refuses_challenges_it_did_not_issue_to_the_sender() {
+    let auth = auth(&["secret"]);
+    let made_up = signed("secret", &format!("{:032x}", 42), BODY);
+    assert!(matches!(
+      verify(&auth, &made_up, address(1)),
+      Err(Rejection::Replayed)
+    ));
+    let challenge = auth.issue_challenge(address(1)).unwrap();
+    let headers = signed("secret", &challenge, BODY);
+    assert!(matches!(
+      verify(&auth, &headers, address(2)),
+      Err(Rejection::Replayed)
+    ));
+  }
+
+  #[test]
+  fn refuses_expired_challenges() {
+    let auth = auth(&["secret"]);
+    let challenge = auth.issue_challenge(address(1)).unwrap();
+    let issued_at = Instant::now().checked_sub(CHALLENGE_TTL).unwrap();
+    auth
+      .issued
+      .lock()
+      .unwrap()
+      .get_mut(&address(1))
+      .unwrap()
+      .insert(challenge.clone(), issued_at);
+    assert!(matches!(
+      verify(&auth, &signed("secret", &challenge, BODY), address(1)),
+      Err(Rejection::Replayed)
+    ));
+  }
+
+  #[test]
+  fn accepts_the_old_key_while_keys_are_rotated() {
+    let rotating = auth(&["new", "old"]);
+    for key in ["new", "old"] {
+      let challenge = rotating.issue_challenge(address(1)).unwrap();
+      assert!(verify(&rotating, &signed(key, &challenge, BODY), address(1)).is_ok());
+    }
+    let rotated = auth(&["new"]);
+    let challenge = rotated.issue_challenge(address(1)).unwrap();
+    assert!(matches!(
+      verify(&rotated, &signed("old", &challenge, BODY), address(1)),
+      Err(Rejection::BadSignature)
+    ));
+  }
+
+  #[test]
+  fn replies_are_signed_apart_from_requests() {
+    let signer = auth(&["old", "new"]);
+    let verifier = auth(&["new", "old"]);
+    let mut headers = HeaderMap::new();
+    let reply = signer.sign_reply("challenge", PATH, BODY).unwrap();
+    headers.insert(SIGNATURE_HEADER, reply);
+    assert!(
+      verifier
+        .verify_reply(&headers, "challenge", PATH, BODY)
+        .is_ok()
+    );
+    let request = signed("new", "challenge", BODY);
+    assert!(matches!(
+      verifier.verify_reply(&request, "challenge", PATH, BODY),
+      Err(Rejection::BadSignature)
+    ));
+  }
+
+  #[test]
+  fn a_flood_of_challenges_only_pushes_out_the_flooders_own() {
+    let auth = auth(&["secret"]);
+    let peer = auth.issue_challenge(address(1)).unwrap();
+    let flooder = auth.issue_challenge(address(2)).unwrap();
+    for _ in 0..MAX_CHALLENGES_PER_ADDRESS {
+      auth.issue_challenge(address(2)).unwrap();
+    }
+    assert!(verify(&auth, &signed("secret", &peer, BODY), address(1)).is_ok());
+    assert!(matches!(
+      verify(&auth, &signed("secret", &flooder, BODY), address(2)),
+      Err(Rejection::Replayed)
+    ));
+  }
+
+  #[test]
+  fn refuses_new_addresses_once_too_many_hold_challenges() {
+    let auth = auth(&["secret"]);
+    for index in 0..MAX_CHALLENGE_ADDRESSES as u32 {
+      let address = IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + index));
+      auth.issue_challenge(address).unwrap();
+    }
+    assert!(auth.issue_challenge(address(0)).is_some());
+    assert!(
+      auth
+        .issue_challenge(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)))
+        .is_none()
+    );
+  }
+
+  async fn challenge(
+    State(auth): State<GossipAuth>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+  ) -> String {
+    auth.issue_challenge(peer.address.ip()).unwrap()
+  }
+
+  /// Serves `/gossip`, echoing what it is sent, behind `auth`.
+  async fn serve(auth: GossipAuth) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let router = Router::new()
+      .route(
+        PATH,
+        post(|Json(body): Json<Value>| async move { Json(body) }),
+      )
+      .route_layer(middleware::from_fn_with_state(auth.clone(), verify_request))
+      .route("/gossip/challenge", get(challenge))
+      .with_state(auth);
+    let listener = MeshListener::new(listener, None).unwrap();
+    tokio::spawn(async move {
+      axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<Peer>(),
+      )
+      .await
+    });
+    address
+  }
+
+  async fn send(client: &GossipAuth, server: SocketAddr) -> eyre::Result<Value> {
+    let url = client.url(&server.to_string(), PATH);
+    let request = reqwest::Client::new().post(url);
+    let reply = client
+      .send(
+        request,
+        &NodeId::from("b"),
+        Wire::default(),
+        &json!({"from": "a"}),
+      )
+      .await?;
+    reply.decode()
+  }
+
+  #[tokio::test]
+  async fn exchanges_signed_gossip_while_keys_are_rotated() {
+    // A new key is added everywhere first, then made the signing key.
+    for (server, client) in [
+      (&["old", "new"], &["old"]),
+      (&["new", "old"], &["old", "new"]),
+    ] as [(&[&str], &[&str]); 2]
+    {
+      let server = serve(auth(server)).await;
+      let client = auth(client);
+      for _ in 0..3 {
+        assert_eq!(send(&client, server).await.unwrap(), json!({"from": "a"}));
+      }
+    }
+  }
+
+  #[tokio::test]
+  async fn refuses_requests_signed_with_an_unknown_key() {
+    let server = serve(auth(&["secret"])).await;
+    assert!(send(&auth(&["other"]), server).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn refuses_replies_signed_with_an_unknown_key() {
+    let server = serve(auth(&["other", "secret"])).await;
+    let client = auth(&["secret"]);
+    assert!(send(&client, server).await.is_err());
+    assert_eq!(client.rejections().bad_signature, 1);
+  }
+}
@@ -0,0 +1,596 @@
+use axum::body::Bytes;
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::http::header::{ACCEPT, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::convert::Infallible;
+use std::io::{Read, Write};
+
+/// The largest message accepted, after decompression.
+pub const MAX_BODY: usize = 16 * 1024 * 1024;
+/// Messages smaller than this are sent uncompressed, as compressing them
+/// costs more than it saves.
+const COMPRESSION_THRESHOLD: usize = 4 * 1024;
+const ZSTD_LEVEL: i32 = 3;
+
+/// How gossip messages are serialized. JSON is what every version of the
+/// node understands; MessagePack is smaller and cheaper to produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+  #[default]
+  Json,
+  MsgPack,
+}
+
+impl Codec {
+  /// Supported codecs, most preferred first.
+  pub const ALL: [Codec; 2] = [Codec::MsgPack, Codec::Json];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Codec::Json => "json",
+      Codec::MsgPack => "msgpack",
+    }
+  }
+
+  pub fn content_type(self) -> &'static str {
+    match self {
+      Codec::Json => "application/json",
+      Codec::MsgPack => "application/msgpack",
+    }
+  }
+
+  fn from_content_type(content_type: &str) -> Option<Self> {
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    Self::ALL
+      .into_iter()
+      .find(|codec| essence.eq_ignore_ascii_case(codec.content_type()))
+  }
+}
+
+/// How large gossip messages are compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+  Zstd,
+  Gzip,
+}
+
+impl Compression {
+  /// Supported compressions, most preferred first.
+  pub const ALL: [Compression; 2] = [Compression::Zstd, Compression::Gzip];
+
+  /// The name used for it in `Content-Encoding`.
+  pub fn name(self) -> &'static str {
+    match self {
+      Compression::Zstd => "zstd",
+      Compression::Gzip => "gzip",
+    }
+  }
+
+  fn from_name(name: &str) -> Option<Self> {
+    Self::ALL
+      .into_iter()
+      .find(|compression| name.trim().eq_ignore_ascii_case(compression.name()))
+  }
+
+  fn compress(self, body: &[u8]) -> eyre::Result<Vec<u8>> {
+    Ok(match self {
+      Compression::Zstd => zstd::encode_all(body, ZSTD_LEVEL)?,
+      Compression::Gzip => {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(body)?;
+        encoder.finish()?
+      },
+    })
+  }
+
+  /// Decompresses at most `MAX_BODY` bytes, so that a small message can't
+  /// expand into an arbitrarily large one.
+  fn decompress(self, body: &[u8]) -> eyre::Result<Vec<u8>> {
+    let reader: Box<dyn Read> = match self {
+      Compression::Zstd => Box::new(zstd::Decoder::new(body)?),
+      Compression::Gzip => Box::new(GzDecoder::new(body)),
+    };
+    let mut decompressed = Vec::new();
+    reader
+      .take(MAX_BODY as u64 + 1)
+      .read_to_end(&mut decompressed)?;
+    if decompressed.len() > MAX_BODY {
+      eyre::bail!("Decompressed message exceeds {} bytes", MAX_BODY);
+    }
+    Ok(decompressed)
+  }
+}
+
+/// How a message is put on the wire: a codec, and a compression for large
+/// messages. Senders use the best one the receiving peer advertises, and
+/// plain JSON for peers that advertise nothing, so that nodes of different
+/// versions can gossip with each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Wire {
+  pub codec: Codec,
+  pub compression: Option<Compression>,
+}
+
+impl Wire {
+  /// The best wire format for a peer that understands the named codecs and
+  /// compressions.
+  pub fn negotiate<'a>(
+    codecs: impl IntoIterator<Item = &'a str>,
+    compressions: impl IntoIterator<Item = &'a str>,
+  ) -> Self {
+    let codecs: Vec<_> = codecs.into_iter().map(str::trim).collect();
+    let compressions: Vec<_> = compressions.into_iter().map(str::trim).collect();
+    let codec = Codec::ALL
+      .into_iter()
+      .find(|codec| codecs.contains(&codec.name()))
+      .unwrap_or_default();
+    let compression = Compression::ALL
+      .into_iter()
+      .find(|compression| compressions.contains(&compression.name()));
+    Self { codec, compression }
+  }
+
+  /// The codecs this node understands, as advertised to peers.
+  pub fn codecs() -> Vec<&'static str> {
+    Codec::ALL.into_iter().map(Codec::name).collect()
+  }
+
+  /// The compressions this node understands, as advertised to peers.
+  pub fn compressions() -> Vec<&'static str> {
+    Compression::ALL
+      .into_iter()
+      .map(Compression::name)
+      .collect()
+  }
+
+  /// The wire format of a message, from its `Content-Type` and
+  /// `Content-Encoding`; `None` if either is not supported. A message
+  /// without a `Content-Type` is taken to be JSON.
+  pub fn of_content(headers: &HeaderMap) -> Option<Self> {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let codec = match header(CONTENT_TYPE) {
+      Some(content_type) => Codec::from_content_type(content_type)?,
+      None => Codec::Json,
+    };
+    let compression = match header(CONTENT_ENCODING) {
+      None | Some("identity") => None,
+      Some(encoding) => Some(Compression::from_name(encoding)?),
+    };
+    Some(Self { codec, compression })
+  }
+
+  /// The wire format a request asks its response to be sent in, from its
+  /// `Accept` and `Accept-Encoding`.
+  pub fn of_accept(headers: &HeaderMap) -> Self {
+    let accepted = |name| {
+      headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|item| {
+          item
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string()
+        })
+        .collect::<Vec<_>>()
+    };
+    let types = accepted(ACCEPT);
+    let codec = Codec::ALL
+      .into_iter()
+      .find(|codec| types.iter().any(|t| t == codec.content_type()))
+      .unwrap_or_default();
+    let encodings = accepted(ACCEPT_ENCODING);
+    let compression = Compression::ALL
+      .into_iter()
+      .find(|compression| encodings.iter().any(|e| e == compression.name()));
+    Self { codec, compression }
+  }
+
+  /// Serializes `body`, compressing it if it is large enough.
+  pub fn encode<T: Serialize>(&self, body: &T) -> eyre::Result<Encoded> {
+    let bytes = match self.codec {
+      Codec::Json => serde_json::to_vec(body)?,
+      Codec::MsgPack => rmp_serde::to_vec_named(body)?,
+    };
+    let compression = self
+      .compression
+      .filter(|_| bytes.len() >= COMPRESSION_THRESHOLD);
+    let bytes = match compression {
+      Some(compression) => compression.compress(&bytes)?,
+      None => bytes,
+    };
+    Ok(Encoded {
+      codec: self.codec,
+      compression,
+      bytes,
+    })
+  }
+
+  pub fn decode<T: DeserializeOwned>(&self, body: &[u8]) -> eyre::Result<T> {
+    let decompressed;
+    let body = match self.compression {
+      Some(compression) => {
+        decompressed = compression.decompress(body)?;
+        &decompressed[..]
+      },
+      None => body,
+    };
+    Ok(match self.codec {
+      Codec::Json => serde_json::from_slice(body)?,
+      Codec::MsgPack => rmp_serde::from_slice(body)?,
+    })
+  }
+
+  /// A response carrying `body` in this wire format.
+  pub fn reply<T: Serialize>(&self, body: &T) -> Response {
+    match self.encode(body) {
+      Ok(encoded) => {
+        let mut headers = HeaderMap::new();
+        for (name, value) in encoded.headers() {
+          headers.insert(name, value);
+        }
+        (headers, encoded.bytes).into_response()
+      },
+      Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+  }
+}
+
+/// Reads the wire format a response is asked for out of the request.
+impl<S: Send + Sync> FromRequestParts<S> for Wire {
+  type Rejection = Infallible;
+
+  async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    Ok(Wire::of_accept(&parts.headers))
+  }
+}
+
+/// A serialized message, ready to send.
+#[derive(Debug)]
+pub struct Encoded {
+  codec: Codec,
+  compression: Option<Compression>,
+  pub bytes: Vec<u8>,
+}
+
+impl Encoded {
+  fn headers(&self) -> Vec<(axum::http::HeaderName, HeaderValue)> {
+    let mut headers = vec![(
+      CONTENT_TYPE,
+      HeaderValue::from_static(self.codec.content_type()),
+    )];
+    if let Some(compression) = self.compression {
+      headers.push((
+        CONTENT_ENCODING,
+        HeaderValue::from_static(compression.name()),
+      ));
+    }
+    headers
+  }
+
+  /// Puts the message in a request, and asks for the response in whichever
+  /// format this node prefers.
+  pub fn apply(self, request: &mut reqwest::Request) {
+    let headers = request.headers_mut();
+    for (name, value) in self.headers() {
+      headers.insert(name, value);
+    }
+    let types: Vec<_> = Codec::ALL.into_iter().map(Codec::content_type).collect();
+    headers.insert(ACCEPT, HeaderValue::from_str(&types.join(", ")).unwrap());
+    headers.insert(
+      ACCEPT_ENCODING,
+      HeaderValue::from_str(&Wire::compressions().join(", ")).unwrap(),
+    );
+    *request.body_mut() = Some(self.bytes.into());
+  }
+}
+
+/// Extracts a gossip message in any supported wire format, like `Json` does
+/// for JSON alone.
+#[derive(Debug)]
+pub struct Gossip<T>(pub T);
+
+impl<T: DeserializeOwned, S: Send + Sync> FromRequest<S> for Gossip<T> {
+  type Rejection = Response;
+
+  async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+    let Some(wire) = Wire::of_content(request.headers()) else {
+      return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response());
+    };
+    let body = Bytes::from_request(request, state)
+      .await
+      .map_err(IntoResponse::into_response)?;
+    wire
+      .decode(&body)
+      .map(Gossip)
+      .map_err(|error| (StatusCode::BAD_REQUEST, error.to_string()).into_response())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::crdts::hlc::Hlc;
+  use crate::crdts::last_write_wins::Entry;
+  use crate::crdts::mv_register::MvRegister;
+  use crate::flags::flag::Flag;
+  use crate::flags::segment::Segment;
+  use crate::gossip::state::GossipPayload;
+  use crate::node::{NodeId, NodeState, NodeStatus};
+  use serde_json::json;
+
+  fn at(physical_ms: u64, node: &str) -> Hlc {
+    Hlc {
+      physical_ms,
+      logical: 7,
+      node: NodeId::from(node),
+    }
+  }
+
+  /// A flag with a variant of every shape, and rules and clauses that carry
+  /// arbitrary JSON.
+  fn flag() -> Flag {
+    serde_json::from_value(json!({
+      "key": "checkout",
+      "description": "New checkout",
+      "enabled": true,
+      "kind": "json",
+      "variants": [
+        {"id": "object", "value": {"theme": "dark", "limits": [1, 2.5, null], "beta": true}},
+        {"id": "empty", "value": {}},
+      ],
+      "default_variant": "object",
+      "prerequisites": [{"flag": "login", "variant": "on"}],
+      "targets": [{"variant": "empty", "keys": ["user-1", "user-2"]}],
+      "rules": [
+        {
+          "id": "rule-1",
+          "clauses": [
+            {"attribute": "plan", "operator": "in", "values": ["pro", 3, 4.5, true, null]},
+            {
+              "attribute": "version",
+              "operator": "semver_greater_than",
+              "values": ["1.2.3"],
+              "negate": true,
+            },
+          ],
+          "serve": {"rollout": {"bucket_by": "org", "variants": [
+            {"variant": "object", "weight": 40000},
+            {"variant": "empty", "weight": 60000},
+          ]}},
+        },
+        {"id": "rule-2", "serve": {"variant": "empty"}},
+      ],
+      "salt": "salt-1",
+      "version": 3,
+      "updated_at": {"physical_ms": 1_700_000_000_000u64, "logical": 2, "node": "a"},
+      "updated_by": "a",
+    }))
+    .unwrap()
+  }
+
+  fn scalar_flags() -> Vec<Flag> {
+    [
+      ("boolean", json!(true), json!(false)),
+      ("number", json!(-3), json!(0.25)),
+      ("string", json!("blue"), json!("")),
+    ]
+    .into_iter()
+    .map(|(kind, first, second)| {
+      let mut flag = flag();
+      flag.key = kind.into();
+      flag.kind = serde_json::from_value(json!(kind)).unwrap();
+      flag.variants = serde_json::from_value(json!([
+        {"id": "first", "value": first},
+        {"id": "second", "value": second},
+      ]))
+      .unwrap();
+      flag.default_variant = "first".to_string();
+      flag
+    })
+    .collect()
+  }
+
+  fn segment() -> Segment {
+    serde_json::from_value(json!({
+      "key": "staff",
+      "description": "",
+      "included": ["user-1"],
+      "excluded": [],
+      "rules": [{"id": "email", "clauses": [
+        {"attribute": "email", "operator": "ends_with", "values": ["@example.com"]},
+      ]}],
+      "version": 1,
+      "updated_at": {"physical_ms": 1_700_000_000_001u64, "logical": 0, "node": "b"},
+      "updated_by": "b",
+    }))
+    .unwrap()
+  }
+
+  /// A payload with an entry of every kind, and enough of them that it is
+  /// compressed when a compression is negotiated.
+  fn payload() -> GossipPayload {
+    let node = NodeId::from("a");
+    let mut diffs: Vec<_> = (0..100)
+      .map(|index| {
+        let id = NodeId::from(format!("node-{}", index).as_str());
+        let address = format!("10.0.{}.{}:7000", index / 250, index % 250 + 1);
+        let state = NodeState::new(&id, at(index, "a"), address.parse().unwrap())
+          .with_status(NodeStatus::Suspect, at(index + 1, "a"));
+        (id, Entry::Value(state))
+      })
+      .collect();
+    diffs.push((
+      NodeId::from("gone"),
+      Entry::Tombstone {
+        deleted_at: at(5, "b"),
+      },
+    ));
+    let mut flags: Vec<_> = std::iter::once(flag())
+      .chain(scalar_flags())
+      .map(|flag| {
+        let mut register = MvRegister::default();
+        register.write(&node, Entry::Value(flag.clone()));
+        (flag.key.clone(), register)
+      })
+      .collect();
+    let mut deleted = MvRegister::default();
+    deleted.write(
+      &node,
+      Entry::Tombstone {
+        deleted_at: at(9, "a"),
+      },
+    );
+    flags.push(("deleted".into(), deleted));
+    GossipPayload {
+      from: node,
+      cluster: "lab".to_string(),
+      clock: at(1_700_000_000_002, "a"),
+      diffs,
+      flags,
+      segments: vec![
+        (segment().key.clone(), Entry::Value(segment())),
+        (
+          "old".into(),
+          Entry::Tombstone {
+            deleted_at: at(3, "b"),
+          },
+        ),
+      ],
+    }
+  }
+
+  /// Encodes the payload, reads the wire format back off the headers it is
+  /// sent with, and decodes it in that format.
+  fn round_trip(wire: Wire) -> (Option<Compression>, GossipPayload) {
+    let encoded = wire.encode(&payload()).unwrap();
+    let mut headers = HeaderMap::new();
+    for (name, value) in encoded.headers() {
+      headers.insert(name, value);
+    }
+    let received = Wire::of_content(&headers).unwrap();
+    assert_eq!(received.codec, wire.codec);
+    (
+      received.compression,
+      received.decode(&encoded.bytes).unwrap(),
+    )
+  }
+
+  fn assert_same(decoded: &GossipPayload, original: &GossipPayload) {
+    assert_eq!(decoded.from, original.from);
+    assert_eq!(decoded.cluster, original.cluster);
+    assert_eq!(decoded.clock, original.clock);
+    assert_eq!(decoded.diffs, original.diffs);
+    assert_eq!(decoded.flags, original.flags);
+    assert_eq!(decoded.segments, original.segments);
+  }
+
+  #[test]
+  fn round_trips_a_populated_payload_in_json() {
+    let wire = Wire {
+      codec: Codec::Json,
+      compression: None,
+    };
+    let (compression, decoded) = round_trip(wire);
+    assert_eq!(compression, None);
+    assert_same(&decoded, &payload());
+  }
+
+  #[test]
+  fn round_trips_a_populated_payload_in_msgpack() {
+    let wire = Wire {
+      codec: Codec::MsgPack,
+      compression: None,
+    };
+    let (compression, decoded) = round_trip(wire);
+    assert_eq!(compression, None);
+    assert_same(&decoded, &payload());
+  }
+
+  #[test]
+  fn round_trips_through_each_compression() {
+    for codec in Codec::ALL {
+      for compression in Compression::ALL {
+        let wire = Wire {
+          codec,
+          compression: Some(compression),
+        };
+        let (received, decoded) = round_trip(wire);
+        assert_eq!(received, Some(compression), "{:?}", wire);
+        assert_same(&decoded, &payload());
+      }
+    }
+  }
+
+  #[test]
+  fn leaves_small_messages_uncompressed() {
+    let wire = Wire {
+      codec: Codec::Json,
+      compression: Some(Compression::Zstd),
+    };
+    let encoded = wire.encode(&json!({"ok": true})).unwrap();
+    assert_eq!(encoded.compression, None);
+    assert_eq!(encoded.bytes, b"{\"ok\":true}");
+  }
+
+  #[test]
+  fn refuses_messages_that_decompress_past_the_limit() {
+    for compression in Compression::ALL {
+      let bomb = compression.compress(&vec![b' '; MAX_BODY + 1]).unwrap();
+      assert!(compression.decompress(&bomb).is_err(), "{:?}", compression);
+    }
+  }
+
+  #[test]
+  fn negotiates_the_best_format_both_sides_share() {
+    let wire = Wire::negotiate(["json", "msgpack"], ["gzip", "zstd"]);
+    assert_eq!(wire.codec, Codec::MsgPack);
+    assert_eq!(wire.compression, Some(Compression::Zstd));
+    let wire = Wire::negotiate(["json"], ["gzip"]);
+    assert_eq!(wire.codec, Codec::Json);
+    assert_eq!(wire.compression, Some(Compression::Gzip));
+  }
+
+  #[test]
+  fn falls_back_to_plain_json_when_nothing_is_shared() {
+    assert_eq!(Wire::negotiate(["cbor"], ["br"]), Wire::default());
+    assert_eq!(Wire::negotiate([], []), Wire::default());
+    assert_eq!(Wire::of_accept(&HeaderMap::new()), Wire::default());
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT, HeaderValue::from_static("application/cbor"));
+    headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("br"));
+    assert_eq!(Wire::of_accept(&headers), Wire::default());
+  }
+
+  #[test]
+  fn reads_the_format_a_message_was_sent_in() {
+    assert_eq!(Wire::of_content(&HeaderMap::new()), Some(Wire::default()));
+    let mut headers = HeaderMap::new();
+    headers.insert(
+      CONTENT_TYPE,
+      HeaderValue::from_static("application/msgpack; charset=binary"),
+    );
+    headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    assert_eq!(
+      Wire::of_content(&headers),
+      Some(Wire {
+        codec: Codec::MsgPack,
+        compression: Some(Compression::Gzip),
+      })
+    );
+    headers.insert(CONTENT_ENCODING, HeaderValue::from_static("br"));
+    assert_eq!(Wire::of_content(&headers), None);
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+    assert_eq!(Wire::of_content(&headers), None);
+  }
+}
@@ -0,0 +1,61 @@
+use super::whisperer::{build_gossip_payload, select_gossip_targets, send_gossip};
+use crate::crdts::last_write_wins::Entry;
+use crate::shutdown::container::ShutdownContainer;
+use futures::future::join_all;
+use std::time::Duration;
+use tokio::time::timeout;
+use tracing::{debug, info, instrument};
+
+/// How many peers hear directly that this node is leaving.
+const LEAVE_FANOUT: usize = 3;
+/// How long to wait for them to acknowledge it.
+const LEAVE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Tells a few peers, along with any gossip still pending, that this node is
+/// leaving, so that they stop sending to it straight away instead of waiting
+/// for it to fail. Meant to run before the listener shuts down.
+#[instrument]
+pub async fn announce_leave(container: &ShutdownContainer) {
+  let app = &container.gossip_state;
+  let client = &container.http_client;
+  let Some(state) = app.leave().await else {
+    debug!("Leaving before the mesh knew about this node");
+    return;
+  };
+  let mut payload = build_gossip_payload(app).await;
+  if !payload.diffs.iter().any(|(id, _)| id == app.id()) {
+    payload.diffs.push((app.id().clone(), Entry::Value(state)));
+  }
+  let targets = select_gossip_targets(app, LEAVE_FANOUT);
+  let sends = targets.iter().map(|(id, address)| {
+    let payload = &payload;
+    async move {
+      match timeout(
+        LEAVE_TIMEOUT,
+        send_gossip(client, app, id, address, payload),
+      )
+      .await
+      {
+        Ok(Ok(())) => true,
+        Ok(Err(error)) => {
+          debug!("Failed to tell {} about leaving: {}", id, error);
+          false
+        },
+        Err(_) => {
+          debug!("Timed out telling {} about leaving", id);
+          false
+        },
+      }
+    }
+  });
+  let acknowledged = join_all(sends).await.into_iter().filter(|ack| *ack).count();
+  info!(
+    "Announced leaving to {} of {} peers",
+    acknowledged,
+    targets.len()
+  );
+  if acknowledged == 0 {
+    // Whatever gossip rounds are left may still get the batch out.
+    app.requeue(payload).await;
+  }
+}
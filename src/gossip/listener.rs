@@ -1,18 +1,34 @@
-use super::state::{GossipPayload, GossipState};
+use super::auth;
+use super::codec::{Gossip, MAX_BODY, Wire};
+use super::state::{
+  GossipPayload, GossipState, SyncRequest, SyncResponse, TreeRequest, default_cluster,
+};
+use super::tls::{self, MeshListener, Peer};
+use crate::api;
+use crate::crdts::merkle::check_children;
+use crate::node::NodeId;
 use crate::shutdown::container::ShutdownContainer;
-use axum::{Json, extract::State};
+use axum::extract::DefaultBodyLimit;
+use axum::response::Response;
+use axum::{
+  Extension, Json,
+  extract::{ConnectInfo, State},
+  http::StatusCode,
+};
 use axum::{
-  Router,
+  Router, middleware,
   routing::{get, post},
 };
+use serde::Deserialize;
 use serde_json::json;
+use std::net::SocketAddr;
 use std::time::Duration;
 use tokio::net::TcpListener;
 use tokio_util::sync::CancellationToken;
 use tower::ServiceBuilder;
 use tower_http::{timeout::TimeoutLayer, trace::TraceLayer};
 
-use tracing::{debug, instrument};
+use tracing::{debug, instrument, warn};
 
 pub async fn gossip_listen(
   container: &ShutdownContainer,
@@ -20,15 +36,42 @@ pub async fn gossip_listen(
   cancel_token: CancellationToken,
 ) -> eyre::Result<()> {
   let gossip_state = container.gossip_state.clone();
+  let http_client = container.http_client.clone();
   let layer = ServiceBuilder::new()
     .layer(TraceLayer::new_for_http())
     .layer(TimeoutLayer::new(Duration::from_secs(5)));
-  let app = Router::new()
+  if !gossip_state.auth().is_enabled() {
+    warn!("No gossip keys configured; accepting unsigned gossip");
+  }
+  let tls = gossip_state.auth().tls();
+  if tls.is_none() {
+    warn!("No TLS configured; gossiping over plain HTTP");
+  }
+  let listener = MeshListener::new(listener, tls)?;
+  let verify = middleware::from_fn_with_state(gossip_state.auth().clone(), auth::verify_request);
+  let gossip = Router::new()
     .route("/gossip", post(gossip_handler))
-    .route("/health", get(|| async { Json(json!({"status": "ok"})) }))
+    .route("/gossip/sync", post(sync_handler))
+    .route("/gossip/tree", post(tree_handler))
+    .merge(super::swim::router())
+    .route_layer(verify)
+    .route("/gossip/challenge", get(challenge_handler))
+    .route_layer(middleware::from_fn(tls::require_peer_cert))
+    .layer(DefaultBodyLimit::max(MAX_BODY));
+  let app = Router::new()
+    .merge(gossip)
+    .route("/health", get(health_handler))
+    .merge(api::evaluate::router())
+    .merge(api::admin::auth::router())
+    .merge(api::admin::flags::router())
+    .merge(api::admin::merkle::router())
+    .merge(api::admin::segments::router())
+    .merge(api::stream::router())
     .layer(layer)
+    .layer(Extension(cancel_token.clone()))
+    .layer(Extension(http_client))
     .with_state(gossip_state);
-  axum::serve(listener, app)
+  axum::serve(listener, app.into_make_service_with_connect_info::<Peer>())
     .with_graceful_shutdown(shutdown_signal(cancel_token))
     .await
     .unwrap();
@@ -39,15 +82,132 @@ async fn shutdown_signal(cancel_token: CancellationToken) {
   cancel_token.cancelled().await;
 }
 
+/// What a peer reports about itself on `/health`. Everything but the ID may
+/// be missing from older nodes.
+#[derive(Debug, Deserialize)]
+pub struct Health {
+  pub node_id: NodeId,
+  #[serde(default = "default_cluster")]
+  pub cluster: String,
+  #[serde(default)]
+  pub addresses: Vec<SocketAddr>,
+  #[serde(default)]
+  pub codecs: Vec<String>,
+  #[serde(default)]
+  pub compressions: Vec<String>,
+}
+
+impl Health {
+  /// The best wire format the peer takes.
+  pub fn wire(&self) -> Wire {
+    Wire::negotiate(
+      self.codecs.iter().map(String::as_str),
+      self.compressions.iter().map(String::as_str),
+    )
+  }
+}
+
+/// Issues a challenge for the next signed gossip request to this node from
+/// the caller's address.
+pub async fn challenge_handler(
+  State(app): State<GossipState>,
+  ConnectInfo(peer): ConnectInfo<Peer>,
+) -> Result<String, StatusCode> {
+  app
+    .auth()
+    .issue_challenge(peer.address.ip())
+    .ok_or_else(|| {
+      warn!(
+        "Refused a challenge to {}: too many addresses hold one",
+        peer.address
+      );
+      StatusCode::SERVICE_UNAVAILABLE
+    })
+}
+
+/// Reports the node as up, along with its ID, addresses and the wire formats
+/// it takes, so that peers found only by address can tell who they reached.
+pub async fn health_handler(State(app): State<GossipState>) -> Json<serde_json::Value> {
+  let addresses = app
+    .nodes()
+    .get(app.id())
+    .map(|state| state.addresses())
+    .unwrap_or_default();
+  Json(json!({
+    "status": "ok",
+    "node_id": app.id(),
+    "cluster": app.cluster(),
+    "addresses": addresses,
+    "codecs": Wire::codecs(),
+    "compressions": Wire::compressions(),
+  }))
+}
+
 #[instrument]
 pub async fn gossip_handler(
   State(app): State<GossipState>,
-  Json(payload): Json<GossipPayload>,
-) -> &'static str {
-  let nodes = app.nodes();
+  ConnectInfo(peer): ConnectInfo<Peer>,
+  Gossip(payload): Gossip<GossipPayload>,
+) -> Result<&'static str, StatusCode> {
+  if let Err(error) = app.check_cluster(&payload.from, &payload.cluster) {
+    warn!("Rejected gossip: {}", error);
+    return Err(StatusCode::FORBIDDEN);
+  }
+  if let Err(error) = peer.cert.check(&payload.from) {
+    warn!("Rejected gossip from {}: {}", payload.from, error);
+    return Err(StatusCode::FORBIDDEN);
+  }
   debug!("Received gossip from: {}", payload.from);
-  for (key, incoming) in payload.diffs {
-    nodes.insert(key, incoming).await;
+  app.merge_payload(payload).await;
+  Ok("ok")
+}
+
+/// Answers a peer's anti-entropy digests with what it is missing, and the
+/// keys it should send back.
+#[instrument]
+pub async fn sync_handler(
+  State(app): State<GossipState>,
+  ConnectInfo(peer): ConnectInfo<Peer>,
+  wire: Wire,
+  Gossip(request): Gossip<SyncRequest>,
+) -> Result<Response, StatusCode> {
+  if let Err(error) = app.check_cluster(&request.from, &request.cluster) {
+    warn!("Rejected sync request: {}", error);
+    return Err(StatusCode::FORBIDDEN);
+  }
+  if let Err(error) = peer.cert.check(&request.from) {
+    warn!("Rejected sync request from {}: {}", request.from, error);
+    return Err(StatusCode::FORBIDDEN);
+  }
+  debug!("Received sync request from: {}", request.from);
+  let (payload, wanted) = app.reconcile(&request.digests, request.scope.as_ref());
+  Ok(wire.reply(&SyncResponse { payload, wanted }))
+}
+
+/// Answers a step of a peer's descent of the Merkle trees.
+#[instrument]
+pub async fn tree_handler(
+  State(app): State<GossipState>,
+  ConnectInfo(peer): ConnectInfo<Peer>,
+  wire: Wire,
+  Gossip(request): Gossip<TreeRequest>,
+) -> Result<Response, StatusCode> {
+  if let Err(error) = app.check_cluster(&request.from, &request.cluster) {
+    warn!("Rejected tree request: {}", error);
+    return Err(StatusCode::FORBIDDEN);
+  }
+  if let Err(error) = peer.cert.check(&request.from) {
+    warn!("Rejected tree request from {}: {}", request.from, error);
+    return Err(StatusCode::FORBIDDEN);
+  }
+  for parents in request.parents.iter() {
+    if let Err(error) = check_children(request.level, parents) {
+      warn!("Rejected tree request from {}: {}", request.from, error);
+      return Err(StatusCode::BAD_REQUEST);
+    }
   }
-  "ok"
+  let hashes = app.trees().zip(&request.parents, |tree, parents| {
+    tree.children(request.level, parents)
+  });
+  Ok(wire.reply(&hashes))
 }
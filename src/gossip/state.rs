@@ -1,41 +1,650 @@
-use crate::crdts::last_write_wins::TrackedLwwMap;
-use crate::node::{NodeId, NodeState};
+use super::auth::{GossipAuth, GossipKeys};
+use super::codec::Wire;
+use super::tls::MeshTls;
+use crate::crdts::hlc::{Hlc, HybridClock};
+use crate::crdts::last_write_wins::{Entry, TrackedLwwMap};
+use crate::crdts::merkle::{Maps, MerkleTree};
+use crate::crdts::mv_register::MvRegister;
+use crate::crdts::tracked_map::Digest;
+use crate::crdts::tracked_mv_map::TrackedMvMap;
+use crate::flags::conflict::ConflictMode;
+use crate::flags::event::FlagEvent;
+use crate::flags::flag::{Flag, FlagKey};
+use crate::flags::segment::{Segment, SegmentKey, SegmentSource};
+use crate::node::{NodeId, NodeState, NodeStatus};
+use dashmap::DashMap;
 use derivative::Derivative;
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, MutexGuard, broadcast};
+use tracing::warn;
+
+/// The cluster of nodes not given one, and of peers that predate clusters.
+pub const DEFAULT_CLUSTER: &str = "default";
+
+pub fn default_cluster() -> String {
+  DEFAULT_CLUSTER.to_string()
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GossipPayload {
   pub from: NodeId,
-  pub diffs: Vec<(NodeId, NodeState)>,
+  /// The sender's cluster; payloads from other clusters are refused.
+  #[serde(default = "default_cluster")]
+  pub cluster: String,
+  /// The sender's clock when it built the payload, which is at least as new
+  /// as every entry in it.
+  pub clock: Hlc,
+  pub diffs: Vec<(NodeId, Entry<NodeState>)>,
+  #[serde(default)]
+  pub flags: Vec<(FlagKey, MvRegister<Entry<Flag>>)>,
+  #[serde(default)]
+  pub segments: Vec<(SegmentKey, Entry<Segment>)>,
+}
+
+impl GossipPayload {
+  pub fn is_empty(&self) -> bool {
+    self.diffs.is_empty() && self.flags.is_empty() && self.segments.is_empty()
+  }
+}
+
+/// Hashes of everything a replica holds, for anti-entropy.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Digests {
+  pub nodes: Digest<NodeId>,
+  pub flags: Digest<FlagKey>,
+  pub segments: Digest<SegmentKey>,
+}
+
+/// The keys one side of an anti-entropy exchange needs the other to send.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Wanted {
+  pub nodes: Vec<NodeId>,
+  pub flags: Vec<FlagKey>,
+  pub segments: Vec<SegmentKey>,
+}
+
+impl Wanted {
+  /// Splits the keys into parts of at most `size` keys of each map.
+  pub fn chunks(&self, size: usize) -> Vec<Wanted> {
+    let longest = self
+      .nodes
+      .len()
+      .max(self.flags.len())
+      .max(self.segments.len());
+    (0..longest.div_ceil(size))
+      .map(|index| Wanted {
+        nodes: chunk(&self.nodes, size, index),
+        flags: chunk(&self.flags, size, index),
+        segments: chunk(&self.segments, size, index),
+      })
+      .collect()
+  }
+}
+
+/// The `index`th run of `size` keys, empty past the end.
+fn chunk<K: Clone>(keys: &[K], size: usize, index: usize) -> Vec<K> {
+  keys
+    .chunks(size)
+    .nth(index)
+    .map(<[K]>::to_vec)
+    .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncRequest {
+  pub from: NodeId,
+  #[serde(default = "default_cluster")]
+  pub cluster: String,
+  pub digests: Digests,
+  /// The Merkle buckets the digests cover, if not everything.
+  #[serde(default)]
+  pub scope: Option<Maps<Vec<usize>>>,
+}
+
+/// Asks for the hashes of the Merkle tree nodes at `level` under the given
+/// positions one level up, in each map.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TreeRequest {
+  pub from: NodeId,
+  #[serde(default = "default_cluster")]
+  pub cluster: String,
+  pub level: u32,
+  pub parents: Maps<Vec<usize>>,
+}
+
+/// What the asking side is missing, and what it should send back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncResponse {
+  pub payload: GossipPayload,
+  pub wanted: Wanted,
 }
 
 #[derive(Clone, Derivative)]
 #[derivative(Debug)]
 pub struct GossipState {
   id: NodeId,
+  /// Only nodes of the same cluster are let into the replica.
+  cluster: String,
+  clock: HybridClock,
   nodes: TrackedLwwMap<NodeId, NodeState>,
+  flags: TrackedMvMap<FlagKey, Flag>,
+  conflict_mode: ConflictMode,
+  segments: TrackedLwwMap<SegmentKey, Segment>,
+  writes: Arc<Mutex<()>>,
+  /// Milliseconds since the Unix epoch of the last merged gossip; zero until
+  /// the first one arrives.
+  last_gossip_at: Arc<AtomicU64>,
+  /// Set once this node has announced that it is leaving, so that it does
+  /// not refute its own departure.
+  leaving: Arc<AtomicBool>,
+  /// The addresses this node advertises itself at, as of the last
+  /// `add_self`; what it refutes suspicion with, rather than whatever the
+  /// mesh last heard.
+  addresses: Arc<RwLock<Vec<SocketAddr>>>,
+  /// The address that last got through to each node. Local to this node,
+  /// as which address works depends on where it is dialled from.
+  routes: Arc<DashMap<NodeId, SocketAddr>>,
+  /// The wire format each node has advertised that it takes.
+  wires: Arc<DashMap<NodeId, Wire>>,
+  auth: GossipAuth,
+  flag_events: broadcast::Sender<FlagEvent>,
 }
 
 impl GossipState {
-  pub fn new(id: &NodeId) -> Self {
+  pub fn new(
+    id: &NodeId,
+    cluster: &str,
+    conflict_mode: ConflictMode,
+    gossip_keys: GossipKeys,
+    tls: Option<MeshTls>,
+    max_clock_offset: Duration,
+  ) -> Self {
     let id = id.clone();
+    let cluster = cluster.to_string();
+    let clock = HybridClock::new(&id, max_clock_offset);
     let nodes = TrackedLwwMap::new();
-    Self { id, nodes }
+    let flags = TrackedMvMap::new();
+    let segments = TrackedLwwMap::new();
+    let writes = Arc::new(Mutex::new(()));
+    let last_gossip_at = Arc::new(AtomicU64::new(0));
+    let leaving = Arc::new(AtomicBool::new(false));
+    let addresses = Arc::new(RwLock::new(Vec::new()));
+    let routes = Arc::new(DashMap::new());
+    let wires = Arc::new(DashMap::new());
+    let auth = GossipAuth::new(gossip_keys, tls);
+    let (flag_events, _) = broadcast::channel(256);
+    Self {
+      id,
+      cluster,
+      clock,
+      nodes,
+      flags,
+      conflict_mode,
+      segments,
+      writes,
+      last_gossip_at,
+      leaving,
+      addresses,
+      routes,
+      wires,
+      auth,
+      flag_events,
+    }
   }
 
   pub fn id(&self) -> &NodeId {
     &self.id
   }
 
+  pub fn cluster(&self) -> &str {
+    &self.cluster
+  }
+
+  pub fn auth(&self) -> &GossipAuth {
+    &self.auth
+  }
+
+  /// Refuses a message from `from` if it belongs to another cluster.
+  pub fn check_cluster(&self, from: &NodeId, cluster: &str) -> eyre::Result<()> {
+    if cluster != self.cluster {
+      eyre::bail!("{} is in cluster {}, not {}", from, cluster, self.cluster);
+    }
+    Ok(())
+  }
+
+  /// A clock reading for a local write.
+  pub fn now(&self) -> Hlc {
+    self.clock.now()
+  }
+
+  /// Moves the local clock past a reading from another node, so that local
+  /// writes made afterwards win over everything that node had written.
+  pub fn observe(&self, remote: &Hlc) -> eyre::Result<()> {
+    self.clock.observe(remote)
+  }
+
   pub fn nodes(&self) -> &TrackedLwwMap<NodeId, NodeState> {
     &self.nodes
   }
 
+  pub fn flags(&self) -> &TrackedMvMap<FlagKey, Flag> {
+    &self.flags
+  }
+
+  /// The concurrent edits of `key` the admin API has to have resolved
+  /// before the flag can be edited again; always empty in LWW mode.
+  pub fn flag_conflicts(&self, key: &FlagKey) -> Vec<Entry<Flag>> {
+    if self.conflict_mode == ConflictMode::Lww {
+      return Vec::new();
+    }
+    let siblings = self.flags.siblings(key);
+    // Deletions that crossed each other leave nothing to choose between.
+    if siblings.len() > 1 && siblings.iter().any(|sibling| sibling.value().is_some()) {
+      return siblings;
+    }
+    Vec::new()
+  }
+
+  pub fn segments(&self) -> &TrackedLwwMap<SegmentKey, Segment> {
+    &self.segments
+  }
+
+  /// Merges a flag register received through gossip.
+  pub async fn merge_flag(&self, key: FlagKey, register: MvRegister<Entry<Flag>>) {
+    let previous = self.flags.get(&key);
+    if self.flags.merge(key.clone(), register).await {
+      self.announce_flag(key, previous);
+    }
+  }
+
+  /// Writes a flag over every edit of it this node has seen.
+  pub async fn insert_flag(&self, flag: Flag) {
+    let key = flag.key.clone();
+    let previous = self.flags.get(&key);
+    if self.flags.insert(key.clone(), &self.id, flag).await {
+      self.announce_flag(key, previous);
+    }
+  }
+
+  pub async fn remove_flag(&self, key: &FlagKey) {
+    let previous = self.flags.get(key);
+    if self.flags.remove(key, &self.id, self.now()).await {
+      self.announce_flag(key.clone(), previous);
+    }
+  }
+
+  /// Tells subscribers about a change to the flag that evaluation sees.
+  fn announce_flag(&self, key: FlagKey, previous: Option<Flag>) {
+    let event = match (previous, self.flags.get(&key)) {
+      (None, Some(flag)) => FlagEvent::Created { flag },
+      (Some(previous), Some(flag)) if previous != flag => FlagEvent::Updated { flag },
+      (Some(_), None) => FlagEvent::Deleted { key },
+      _ => return,
+    };
+    // Nobody listening is not an error.
+    let _ = self.flag_events.send(event);
+  }
+
+  pub fn subscribe_flags(&self) -> broadcast::Receiver<FlagEvent> {
+    self.flag_events.subscribe()
+  }
+
+  pub async fn remove_segment(&self, key: &SegmentKey) {
+    self.segments.remove(key, self.now()).await;
+  }
+
+  /// Merges everything in a payload from a peer, whether it was pushed by
+  /// gossip or pulled by anti-entropy.
+  pub async fn merge_payload(&self, payload: GossipPayload) {
+    if let Err(error) = self.observe(&payload.clock) {
+      warn!("Ignoring gossip from {}: {}", payload.from, error);
+      return;
+    }
+    for (key, incoming) in payload.diffs {
+      self.nodes.merge(key, incoming).await;
+    }
+    self.refute_suspicion().await;
+    for (key, incoming) in payload.flags {
+      self.merge_flag(key, incoming).await;
+    }
+    for (key, incoming) in payload.segments {
+      self.segments.merge(key, incoming).await;
+    }
+    self.record_gossip_received();
+  }
+
+  pub fn trees(&self) -> Maps<MerkleTree> {
+    Maps {
+      nodes: self.nodes.tree().clone(),
+      flags: self.flags.tree().clone(),
+      segments: self.segments.tree().clone(),
+    }
+  }
+
+  /// Puts the entries of a payload that reached no peer back in the dirty
+  /// sets, to go out in a later round.
+  pub async fn requeue(&self, payload: GossipPayload) {
+    self.nodes.requeue(payload.diffs).await;
+    self.flags.requeue(payload.flags).await;
+    self.segments.requeue(payload.segments).await;
+  }
+
+  /// Digests of the entries in the Merkle buckets `scope` names, or of all
+  /// of them.
+  pub fn digests(&self, scope: Option<&Maps<Vec<usize>>>) -> Digests {
+    Digests {
+      nodes: self.nodes.digest(scope.map(|scope| &scope.nodes[..])),
+      flags: self.flags.digest(scope.map(|scope| &scope.flags[..])),
+      segments: self.segments.digest(scope.map(|scope| &scope.segments[..])),
+    }
+  }
+
+  /// Compares the replica with a peer's digests of the buckets in `scope`,
+  /// returning what the peer is missing and what it should send back.
+  pub fn reconcile(
+    &self,
+    digests: &Digests,
+    scope: Option<&Maps<Vec<usize>>>,
+  ) -> (GossipPayload, Wanted) {
+    let (diffs, nodes) = self
+      .nodes
+      .reconcile(&digests.nodes, scope.map(|scope| &scope.nodes[..]));
+    let (flags, flag_keys) = self
+      .flags
+      .reconcile(&digests.flags, scope.map(|scope| &scope.flags[..]));
+    let (segments, segment_keys) = self
+      .segments
+      .reconcile(&digests.segments, scope.map(|scope| &scope.segments[..]));
+    let payload = GossipPayload {
+      from: self.id.clone(),
+      cluster: self.cluster.clone(),
+      clock: self.now(),
+      diffs,
+      flags,
+      segments,
+    };
+    let wanted = Wanted {
+      nodes,
+      flags: flag_keys,
+      segments: segment_keys,
+    };
+    (payload, wanted)
+  }
+
+  /// The entries a peer asked for.
+  pub fn payload_for(&self, wanted: &Wanted) -> GossipPayload {
+    GossipPayload {
+      from: self.id.clone(),
+      cluster: self.cluster.clone(),
+      clock: self.now(),
+      diffs: self.nodes.entries(&wanted.nodes),
+      flags: self.flags.entries(&wanted.flags),
+      segments: self.segments.entries(&wanted.segments),
+    }
+  }
+
+  /// Serialises local admin writes so that a version check and the write it
+  /// guards cannot interleave with another local write.
+  pub async fn lock_writes(&self) -> MutexGuard<'_, ()> {
+    self.writes.lock().await
+  }
+
+  pub fn record_gossip_received(&self) {
+    self.last_gossip_at.store(now_millis(), Ordering::Relaxed);
+  }
+
+  pub fn last_gossip_at(&self) -> Option<u64> {
+    match self.last_gossip_at.load(Ordering::Relaxed) {
+      0 => None,
+      at => Some(at),
+    }
+  }
+
   pub async fn add_node(&self, id: &NodeId, node_state: NodeState) {
     self.nodes.insert(id.clone(), node_state).await;
   }
 
+  /// Puts this node in its own membership, so that it gossips itself to the
+  /// peers it finds even where they can't discover it on their own.
+  pub async fn add_self(&self, addresses: &[SocketAddr]) {
+    *self.addresses.write().expect("Addresses lock poisoned") = addresses.to_vec();
+    if let Some(node_state) = self.local_state() {
+      self.add_node(&self.id, node_state).await;
+    }
+  }
+
+  /// This node as it is now, alive at the addresses it advertises.
+  fn local_state(&self) -> Option<NodeState> {
+    let addresses = self.addresses.read().expect("Addresses lock poisoned");
+    let address = addresses.first()?;
+    Some(NodeState::new(&self.id, self.now(), *address).with_addresses(addresses.clone()))
+  }
+
+  /// A node's addresses in the order to try them: the one that last got
+  /// through first, then the rest in the node's own order of preference.
+  pub fn addresses_of(&self, id: &NodeId, state: &NodeState) -> Vec<SocketAddr> {
+    let mut addresses = state.addresses();
+    if let Some(route) = self.routes.get(id)
+      && let Some(index) = addresses.iter().position(|address| *address == *route)
+    {
+      addresses[..=index].rotate_right(1);
+    }
+    addresses
+  }
+
+  /// Remembers that `address` got through to `id`, to try it first next time.
+  pub fn record_route(&self, id: &NodeId, address: SocketAddr) {
+    self.routes.insert(id.clone(), address);
+  }
+
+  /// The wire format to send `id` messages in; plain JSON until it has
+  /// advertised something better.
+  pub fn wire_for(&self, id: &NodeId) -> Wire {
+    self.wires.get(id).map(|wire| *wire).unwrap_or_default()
+  }
+
+  pub fn record_wire(&self, id: &NodeId, wire: Wire) {
+    self.wires.insert(id.clone(), wire);
+  }
+
+  /// Records that a node has gone away as it being dead at its current
+  /// incarnation, rather than with a tombstone, so that node states are only
+  /// ever ordered by incarnation, status and then time; the node refutes it
+  /// if it is in fact still up. `bury_nodes` tombstones it once it has
+  /// stayed dead long enough.
   pub async fn remove_node(&self, id: &NodeId) {
-    self.nodes.remove(id).await;
+    self.mark_node(id, NodeStatus::Dead).await;
+  }
+
+  /// Records a more pessimistic belief about a node at its current
+  /// incarnation, returning whether it changed anything.
+  pub async fn mark_node(&self, id: &NodeId, status: NodeStatus) -> bool {
+    match self.nodes.get(id) {
+      Some(state) if state.status() < status => {
+        let state = state.with_status(status, self.now());
+        self.nodes.insert(id.clone(), state).await
+      },
+      _ => false,
+    }
+  }
+
+  /// Marks this node as having left, returning its final state, if the
+  /// mesh knew about it at all.
+  pub async fn leave(&self) -> Option<NodeState> {
+    self.leaving.store(true, Ordering::Relaxed);
+    self.mark_node(&self.id, NodeStatus::Left).await;
+    self.nodes.get(&self.id)
+  }
+
+  /// Answers the mesh's suspicion that this node is dead, or a view of it
+  /// left over from before a restart at other addresses, by announcing it
+  /// alive where it is now, at a higher incarnation.
+  pub async fn refute_suspicion(&self) {
+    if self.leaving.load(Ordering::Relaxed) {
+      return;
+    }
+    let Some(state) = self.nodes.get(&self.id) else {
+      return;
+    };
+    let local = self.local_state().unwrap_or_else(|| state.clone());
+    if state.is_alive() && state.addresses() == local.addresses() {
+      return;
+    }
+    let refuted = local.refute(state.incarnation(), self.now());
+    self.nodes.insert(self.id.clone(), refuted).await;
+  }
+
+  /// Tombstones other nodes that have been dead or gone for longer than
+  /// `grace`, so that they are collected like any other deletion instead of
+  /// being replicated forever. A node that comes back announces itself with
+  /// a newer reading than the tombstone.
+  pub async fn bury_nodes(&self, grace: Duration) -> usize {
+    let before = self
+      .now()
+      .physical_ms
+      .saturating_sub(grace.as_millis() as u64);
+    let mut buried = 0;
+    for (id, state) in self.nodes.iter() {
+      let gone = matches!(state.status(), NodeStatus::Dead | NodeStatus::Left);
+      if id != self.id && gone && state.last_seen().physical_ms < before {
+        buried += usize::from(self.nodes.remove(&id, self.now()).await);
+      }
+    }
+    buried
+  }
+
+  /// Forgets tombstones older than `grace`, by which time every live peer
+  /// should have heard about the deletion. Their age is measured on the
+  /// hybrid clock they were stamped with, which keeps up with the mesh even
+  /// where this node's wall clock is behind.
+  pub fn purge_tombstones(&self, grace: Duration) -> usize {
+    let before = self
+      .now()
+      .physical_ms
+      .saturating_sub(grace.as_millis() as u64);
+    self.nodes.purge_tombstones(before).len()
+      + self.flags.purge_tombstones(before).len()
+      + self.segments.purge_tombstones(before).len()
+  }
+}
+
+fn now_millis() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .expect("Time went backwards")
+    .as_millis() as u64
+}
+
+impl SegmentSource for GossipState {
+  fn segment(&self, key: &SegmentKey) -> Option<Segment> {
+    self.segments.get(key)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn state() -> GossipState {
+    GossipState::new(
+      &NodeId::from("local"),
+      DEFAULT_CLUSTER,
+      ConflictMode::Lww,
+      GossipKeys::default(),
+      None,
+      Duration::from_secs(60),
+    )
+  }
+
+  fn address(address: &str) -> SocketAddr {
+    address.parse().unwrap()
+  }
+
+  fn at(physical_ms: u64) -> Hlc {
+    Hlc {
+      physical_ms,
+      logical: 0,
+      node: NodeId::from("observer"),
+    }
+  }
+
+  #[tokio::test]
+  async fn refutes_suspicion_at_a_higher_incarnation() {
+    let app = state();
+    app.add_self(&[address("10.0.0.1:7000")]).await;
+    app.mark_node(app.id(), NodeStatus::Suspect).await;
+    app.refute_suspicion().await;
+    let refuted = app.nodes().get(app.id()).unwrap();
+    assert_eq!(refuted.status(), NodeStatus::Alive);
+    assert_eq!(refuted.incarnation(), 1);
+    assert_eq!(refuted.addresses(), vec![address("10.0.0.1:7000")]);
+  }
+
+  #[tokio::test]
+  async fn announces_its_current_addresses_over_a_view_from_before_a_restart() {
+    let app = state();
+    app.add_self(&[address("10.0.0.2:7001")]).await;
+    let stale = NodeState::new(app.id(), app.now(), address("10.0.0.1:7000")).refute(4, app.now());
+    app.add_node(app.id(), stale).await;
+    app.refute_suspicion().await;
+    let refuted = app.nodes().get(app.id()).unwrap();
+    assert_eq!(refuted.status(), NodeStatus::Alive);
+    assert_eq!(refuted.incarnation(), 6);
+    assert_eq!(refuted.addresses(), vec![address("10.0.0.2:7001")]);
+  }
+
+  #[tokio::test]
+  async fn leaves_an_accurate_view_alone() {
+    let app = state();
+    app.add_self(&[address("10.0.0.1:7000")]).await;
+    app.refute_suspicion().await;
+    assert_eq!(app.nodes().get(app.id()).unwrap().incarnation(), 0);
+  }
+
+  #[tokio::test]
+  async fn does_not_refute_its_own_departure() {
+    let app = state();
+    app.add_self(&[address("10.0.0.1:7000")]).await;
+    app.leave().await;
+    app.refute_suspicion().await;
+    assert_eq!(
+      app.nodes().get(app.id()).unwrap().status(),
+      NodeStatus::Left
+    );
+  }
+
+  #[tokio::test]
+  async fn buries_nodes_gone_for_longer_than_the_grace_period() {
+    let app = state();
+    app.add_self(&[address("10.0.0.1:7000")]).await;
+    let node = |id: &str, status| {
+      NodeState::new(&NodeId::from(id), at(1), address("10.0.0.9:7000")).with_status(status, at(1))
+    };
+    for (id, status) in [
+      ("dead", NodeStatus::Dead),
+      ("left", NodeStatus::Left),
+      ("alive", NodeStatus::Alive),
+      ("suspect", NodeStatus::Suspect),
+    ] {
+      app.add_node(&NodeId::from(id), node(id, status)).await;
+    }
+    let recent = NodeId::from("recent");
+    app
+      .add_node(&recent, node("recent", NodeStatus::Alive))
+      .await;
+    app.mark_node(&recent, NodeStatus::Dead).await;
+
+    assert_eq!(app.bury_nodes(Duration::from_secs(60)).await, 2);
+    let mut remaining: Vec<_> = app.nodes().iter().into_iter().map(|(id, _)| id).collect();
+    remaining.sort();
+    let expected = ["alive", "local", "recent", "suspect"].map(NodeId::from);
+    assert_eq!(remaining, expected);
+
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    assert_eq!(app.purge_tombstones(Duration::ZERO), 2);
   }
 }
@@ -0,0 +1,242 @@
+use super::codec::{Gossip, Wire};
+use super::state::{GossipState, default_cluster};
+use super::tls::Peer;
+use super::whisperer::select_gossip_targets;
+use crate::node::{NodeId, NodeState, NodeStatus};
+use crate::shutdown::container::ShutdownContainer;
+use axum::extract::{ConnectInfo, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::{Extension, Router, routing::post};
+use futures::future::join_all;
+use rand::SeedableRng;
+use rand::prelude::IndexedRandom;
+use rand::rngs::SmallRng;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::time::interval;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, instrument, trace, warn};
+
+/// How often a node probes one of its peers.
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a direct ping may take before the target counts as missed.
+const PING_TIMEOUT: Duration = Duration::from_millis(500);
+/// How many peers are asked to ping a target that missed a direct ping.
+const INDIRECT_PROBES: usize = 3;
+/// How long a suspect has to refute the suspicion before it is declared dead.
+const SUSPICION_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn router() -> Router<GossipState> {
+  Router::new()
+    .route("/swim/ping", post(ping_handler))
+    .route("/swim/ping-req", post(ping_req_handler))
+}
+
+/// A probe of `target`, carrying what the prober believes about it, so that
+/// a suspect learns of the suspicion and can refute it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ping {
+  pub from: NodeId,
+  #[serde(default = "default_cluster")]
+  pub cluster: String,
+  pub target: NodeId,
+  pub state: Option<NodeState>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ack {
+  pub from: NodeId,
+}
+
+/// Asks a peer to ping `target` on the sender's behalf.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PingReq {
+  pub ping: Ping,
+  pub address: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PingReqResult {
+  pub ack: bool,
+}
+
+#[instrument]
+pub async fn ping_handler(
+  State(app): State<GossipState>,
+  wire: Wire,
+  Gossip(ping): Gossip<Ping>,
+) -> Result<Response, StatusCode> {
+  // The address may have been taken over by another node.
+  if ping.target != *app.id() {
+    return Err(StatusCode::NOT_FOUND);
+  }
+  if let Err(error) = app.check_cluster(&ping.from, &ping.cluster) {
+    warn!("Rejected ping: {}", error);
+    return Err(StatusCode::FORBIDDEN);
+  }
+  trace!("Pinged by {}", ping.from);
+  if let Some(state) = ping.state {
+    app.nodes().insert(ping.target, state).await;
+    app.refute_suspicion().await;
+  }
+  Ok(wire.reply(&Ack {
+    from: app.id().clone(),
+  }))
+}
+
+/// Pings on a peer's behalf. Unlike a ping, which may come from a helper,
+/// a ping request has to come from the node that sent it.
+#[instrument]
+pub async fn ping_req_handler(
+  State(app): State<GossipState>,
+  ConnectInfo(peer): ConnectInfo<Peer>,
+  Extension(client): Extension<Client>,
+  wire: Wire,
+  Gossip(request): Gossip<PingReq>,
+) -> Result<Response, StatusCode> {
+  if let Err(error) = peer.cert.check(&request.ping.from) {
+    warn!(
+      "Rejected ping request from {}: {}",
+      request.ping.from, error
+    );
+    return Err(StatusCode::FORBIDDEN);
+  }
+  let ack = send_ping(&client, &app, &request.address, &request.ping).await;
+  Ok(wire.reply(&PingReqResult { ack }))
+}
+
+/// Whether `target_addr` acknowledged the ping as the node it was meant for.
+#[instrument]
+pub async fn send_ping(client: &Client, app: &GossipState, target_addr: &str, ping: &Ping) -> bool {
+  let auth = app.auth();
+  let url = auth.url(target_addr, "/swim/ping");
+  let wire = app.wire_for(&ping.target);
+  let request = client.post(url).timeout(PING_TIMEOUT);
+  let reply = match auth.send(request, &ping.target, wire, ping).await {
+    Ok(reply) => reply,
+    Err(error) => {
+      debug!("No ack from {}: {}", target_addr, error);
+      return false;
+    },
+  };
+  matches!(reply.decode::<Ack>(), Ok(ack) if ack.from == ping.target)
+}
+
+#[instrument]
+async fn send_ping_req(
+  client: &Client,
+  app: &GossipState,
+  helper: &NodeId,
+  helper_addr: &str,
+  request: &PingReq,
+) -> bool {
+  let auth = app.auth();
+  let url = auth.url(helper_addr, "/swim/ping-req");
+  let wire = app.wire_for(helper);
+  let Ok(reply) = auth
+    .send(
+      client.post(url).timeout(PING_TIMEOUT * 2),
+      helper,
+      wire,
+      request,
+    )
+    .await
+  else {
+    return false;
+  };
+  matches!(reply.decode::<PingReqResult>(), Ok(result) if result.ack)
+}
+
+/// Pings `id` directly at each of its addresses, then through a few other
+/// peers, and suspects it if no ping gets through.
+#[instrument]
+pub async fn probe(client: &Client, app: &GossipState, id: &NodeId, state: &NodeState) {
+  let ping = Ping {
+    from: app.id().clone(),
+    cluster: app.cluster().to_string(),
+    target: id.clone(),
+    state: Some(state.clone()),
+  };
+  let addresses = app.addresses_of(id, state);
+  for address in &addresses {
+    if send_ping(client, app, &address.to_string(), &ping).await {
+      app.record_route(id, *address);
+      return;
+    }
+  }
+  let address = addresses[0].to_string();
+  let request = PingReq { ping, address };
+  let helpers: Vec<_> = select_gossip_targets(app, INDIRECT_PROBES + 1)
+    .into_iter()
+    .filter(|(helper, _)| helper != id)
+    .take(INDIRECT_PROBES)
+    .collect();
+  let acks = join_all(
+    helpers
+      .iter()
+      .map(|(helper, helper_addr)| send_ping_req(client, app, helper, helper_addr, &request)),
+  )
+  .await;
+  if acks.into_iter().any(|ack| ack) {
+    return;
+  }
+  if app.mark_node(id, NodeStatus::Suspect).await {
+    info!("Suspecting node {}", id);
+  }
+}
+
+/// Declares dead the suspects that have not refuted suspicion in time.
+#[instrument]
+pub async fn expire_suspicions(app: &GossipState) {
+  let now = app.now().physical_ms;
+  let timeout = SUSPICION_TIMEOUT.as_millis() as u64;
+  for (id, state) in app.nodes().iter() {
+    if state.status() == NodeStatus::Suspect
+      && now.saturating_sub(state.last_seen().physical_ms) > timeout
+      && app.mark_node(&id, NodeStatus::Dead).await
+    {
+      info!("Declaring node {} dead", id);
+    }
+  }
+}
+
+/// Picks a peer to probe: any that is alive or suspect, as a suspect has to
+/// hear of the suspicion to refute it.
+fn select_probe_target(app: &GossipState) -> Option<(NodeId, NodeState)> {
+  let candidates: Vec<_> = app
+    .nodes()
+    .iter()
+    .into_iter()
+    .filter(|(id, state)| id != app.id() && state.status() <= NodeStatus::Suspect)
+    .collect();
+  let mut rng = SmallRng::from_os_rng();
+  candidates.choose(&mut rng).cloned()
+}
+
+#[instrument]
+pub async fn swim_probe(
+  container: &ShutdownContainer,
+  cancel: CancellationToken,
+) -> eyre::Result<()> {
+  let app = container.gossip_state.clone();
+  let client = &container.http_client;
+  let mut interval = interval(PROBE_INTERVAL);
+  info!("Starting failure detector...");
+  loop {
+    tokio::select! {
+      biased;
+      _ = cancel.cancelled() => {
+        debug!("Failure detector received shutdown");
+        break Ok(());
+      }
+      _ = interval.tick() => {
+        expire_suspicions(&app).await;
+        if let Some((id, state)) = select_probe_target(&app) {
+          probe(client, &app, &id, &state).await;
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,392 @@
+use crate::node::NodeId;
+use axum::extract::Request;
+use axum::extract::connect_info::{ConnectInfo, Connected};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::serve::{IncomingStream, Listener};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::{verify_server_cert_signed_by_trust_anchor, verify_server_name};
+use rustls::crypto::{
+  WebPkiSupportedAlgorithms, ring, verify_tls12_signature, verify_tls13_signature,
+};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::server::{ParsedCertificate, WebPkiClientVerifier};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+use tokio_util::either::Either;
+use tracing::{debug, warn};
+
+/// How long a connecting peer has to finish the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How many finished handshakes may wait for the server to pick them up.
+const HANDSHAKE_BACKLOG: usize = 64;
+
+/// Mutual TLS between nodes. Every node presents a certificate issued by the
+/// mesh's CA and naming its node ID, and checks that the peer it dialled
+/// presents one naming the ID it expected.
+#[derive(Clone)]
+pub struct MeshTls {
+  client: Arc<ClientConfig>,
+  acceptor: TlsAcceptor,
+}
+
+impl MeshTls {
+  /// Reads the CA bundle, and this node's certificate chain and key, as PEM.
+  pub fn load(ca: &Path, cert: &Path, key: &Path) -> eyre::Result<Self> {
+    let provider = Arc::new(ring::default_provider());
+    let mut roots = RootCertStore::empty();
+    for ca in read_certs(ca)? {
+      roots.add(ca)?;
+    }
+    let roots = Arc::new(roots);
+    let certs = read_certs(cert)?;
+    let key = read_key(key)?;
+
+    let verifier = ChainVerifier {
+      roots: roots.clone(),
+      algorithms: provider.signature_verification_algorithms,
+    };
+    let client = ClientConfig::builder_with_provider(provider.clone())
+      .with_safe_default_protocol_versions()?
+      .dangerous()
+      .with_custom_certificate_verifier(Arc::new(verifier))
+      .with_client_auth_cert(certs.clone(), key.clone_key())?;
+
+    // Clients without a certificate are let through the handshake, so that
+    // the API stays reachable; the gossip routes refuse them.
+    let client_verifier = WebPkiClientVerifier::builder_with_provider(roots, provider.clone())
+      .allow_unauthenticated()
+      .build()?;
+    let server = ServerConfig::builder_with_provider(provider)
+      .with_safe_default_protocol_versions()?
+      .with_client_cert_verifier(client_verifier)
+      .with_single_cert(certs, key)?;
+
+    Ok(Self {
+      client: Arc::new(client),
+      acceptor: TlsAcceptor::from(Arc::new(server)),
+    })
+  }
+
+  pub fn client_config(&self) -> ClientConfig {
+    self.client.as_ref().clone()
+  }
+}
+
+impl std::fmt::Debug for MeshTls {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "MeshTls")
+  }
+}
+
+fn read_certs(path: &Path) -> eyre::Result<Vec<CertificateDer<'static>>> {
+  let file = File::open(path)
+    .map_err(|error| eyre::eyre!("Failed to read {}: {}", path.display(), error))?;
+  let certs = rustls_pemfile::certs(&mut BufReader::new(file)).collect::<Result<Vec<_>, _>>()?;
+  if certs.is_empty() {
+    eyre::bail!("No certificates in {}", path.display());
+  }
+  Ok(certs)
+}
+
+fn read_key(path: &Path) -> eyre::Result<PrivateKeyDer<'static>> {
+  let file = File::open(path)
+    .map_err(|error| eyre::eyre!("Failed to read {}: {}", path.display(), error))?;
+  rustls_pemfile::private_key(&mut BufReader::new(file))?
+    .ok_or_else(|| eyre::eyre!("No private key in {}", path.display()))
+}
+
+/// Checks only that a server's certificate chains to the mesh's CA. Peers
+/// are dialled by address, so the name the certificate has to carry is the
+/// node ID the caller expected, which it checks on the response.
+#[derive(Debug)]
+struct ChainVerifier {
+  roots: Arc<RootCertStore>,
+  algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for ChainVerifier {
+  fn verify_server_cert(
+    &self,
+    end_entity: &CertificateDer<'_>,
+    intermediates: &[CertificateDer<'_>],
+    _server_name: &ServerName<'_>,
+    _ocsp_response: &[u8],
+    now: UnixTime,
+  ) -> Result<ServerCertVerified, rustls::Error> {
+    let cert = ParsedCertificate::try_from(end_entity)?;
+    verify_server_cert_signed_by_trust_anchor(
+      &cert,
+      &self.roots,
+      intermediates,
+      now,
+      self.algorithms.all,
+    )?;
+    Ok(ServerCertVerified::assertion())
+  }
+
+  fn verify_tls12_signature(
+    &self,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+  ) -> Result<HandshakeSignatureValid, rustls::Error> {
+    verify_tls12_signature(message, cert, dss, &self.algorithms)
+  }
+
+  fn verify_tls13_signature(
+    &self,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+  ) -> Result<HandshakeSignatureValid, rustls::Error> {
+    verify_tls13_signature(message, cert, dss, &self.algorithms)
+  }
+
+  fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+    self.algorithms.supported_schemes()
+  }
+}
+
+/// The certificate a peer presented, already chained to the mesh's CA by
+/// the handshake, or `Plain` when TLS is off.
+#[derive(Clone, Debug)]
+pub enum PeerCert {
+  Plain,
+  Tls(Option<CertificateDer<'static>>),
+}
+
+impl PeerCert {
+  /// The certificate the server presented on a response.
+  pub fn of_response(response: &reqwest::Response) -> Self {
+    let Some(info) = response.extensions().get::<reqwest::tls::TlsInfo>() else {
+      return PeerCert::Plain;
+    };
+    let cert = info
+      .peer_certificate()
+      .map(|der| CertificateDer::from(der.to_vec()));
+    PeerCert::Tls(cert)
+  }
+
+  /// Refuses a peer whose certificate is not issued to `id`.
+  pub fn check(&self, id: &NodeId) -> eyre::Result<()> {
+    let cert = match self {
+      PeerCert::Plain => return Ok(()),
+      PeerCert::Tls(None) => eyre::bail!("Peer presented no certificate"),
+      PeerCert::Tls(Some(cert)) => cert,
+    };
+    let name = ServerName::try_from(String::from(id.clone()))
+      .map_err(|_| eyre::eyre!("{} is not a valid certificate name", id))?;
+    verify_server_name(&ParsedCertificate::try_from(cert)?, &name)
+      .map_err(|_| eyre::eyre!("Peer certificate is not issued to {}", id))
+  }
+}
+
+/// Who is on the other end of a gossip connection: the address it connects
+/// from, and the certificate it presented.
+#[derive(Clone, Debug)]
+pub struct Peer {
+  pub address: SocketAddr,
+  pub cert: PeerCert,
+}
+
+impl Connected<IncomingStream<'_, MeshListener>> for Peer {
+  fn connect_info(stream: IncomingStream<'_, MeshListener>) -> Self {
+    let cert = match stream.io() {
+      Either::Left(_) => PeerCert::Plain,
+      Either::Right(stream) => {
+        let cert = stream
+          .get_ref()
+          .1
+          .peer_certificates()
+          .and_then(|certs| certs.first())
+          .cloned();
+        PeerCert::Tls(cert)
+      },
+    };
+    Peer {
+      address: *stream.remote_addr(),
+      cert,
+    }
+  }
+}
+
+/// Refuses gossip over TLS from clients that did not present a certificate.
+pub async fn require_peer_cert(
+  ConnectInfo(peer): ConnectInfo<Peer>,
+  request: Request,
+  next: Next,
+) -> Response {
+  if let PeerCert::Tls(None) = peer.cert {
+    warn!(
+      "Rejected gossip request to {}: no client certificate",
+      request.uri().path()
+    );
+    return StatusCode::UNAUTHORIZED.into_response();
+  }
+  next.run(request).await
+}
+
+/// The gossip listener: plain TCP, or TLS with handshakes done off the
+/// accept loop so that a slow peer doesn't hold up the others.
+pub enum MeshListener {
+  Plain(TcpListener),
+  Tls {
+    local_addr: SocketAddr,
+    handshakes: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+  },
+}
+
+impl MeshListener {
+  pub fn new(listener: TcpListener, tls: Option<&MeshTls>) -> io::Result<Self> {
+    let Some(tls) = tls else {
+      return Ok(MeshListener::Plain(listener));
+    };
+    let local_addr = listener.local_addr()?;
+    let (sender, handshakes) = mpsc::channel(HANDSHAKE_BACKLOG);
+    tokio::spawn(accept_tls(listener, tls.acceptor.clone(), sender));
+    Ok(MeshListener::Tls {
+      local_addr,
+      handshakes,
+    })
+  }
+}
+
+/// Accepts connections and hands each to its own handshake, until the
+/// listener is dropped.
+async fn accept_tls(
+  mut listener: TcpListener,
+  acceptor: TlsAcceptor,
+  sender: mpsc::Sender<(TlsStream<TcpStream>, SocketAddr)>,
+) {
+  loop {
+    let (stream, address) = tokio::select! {
+      _ = sender.closed() => break,
+      accepted = Listener::accept(&mut listener) => accepted,
+    };
+    let acceptor = acceptor.clone();
+    let sender = sender.clone();
+    tokio::spawn(async move {
+      match timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+        Ok(Ok(stream)) => {
+          let _ = sender.send((stream, address)).await;
+        },
+        Ok(Err(error)) => debug!("TLS handshake with {} failed: {}", address, error),
+        Err(_) => debug!("TLS handshake with {} timed out", address),
+      }
+    });
+  }
+}
+
+impl Listener for MeshListener {
+  type Io = Either<TcpStream, TlsStream<TcpStream>>;
+  type Addr = SocketAddr;
+
+  async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+    match self {
+      MeshListener::Plain(listener) => {
+        let (stream, address) = Listener::accept(listener).await;
+        (Either::Left(stream), address)
+      },
+      MeshListener::Tls { handshakes, .. } => match handshakes.recv().await {
+        Some((stream, address)) => (Either::Right(stream), address),
+        None => std::future::pending().await,
+      },
+    }
+  }
+
+  fn local_addr(&self) -> io::Result<Self::Addr> {
+    match self {
+      MeshListener::Plain(listener) => listener.local_addr(),
+      MeshListener::Tls { local_addr, .. } => Ok(*local_addr),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+
+  struct Ca {
+    cert: rcgen::Certificate,
+    key: KeyPair,
+  }
+
+  impl Ca {
+    fn new() -> Self {
+      let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+      params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+      let key = KeyPair::generate().unwrap();
+      let cert = params.self_signed(&key).unwrap();
+      Self { cert, key }
+    }
+
+    fn issue(&self, id: &str) -> CertificateDer<'static> {
+      let params = CertificateParams::new(vec![id.to_string()]).unwrap();
+      let key = KeyPair::generate().unwrap();
+      let cert = params.signed_by(&key, &self.cert, &self.key).unwrap();
+      cert.der().clone()
+    }
+
+    fn verifier(&self) -> ChainVerifier {
+      let mut roots = RootCertStore::empty();
+      roots.add(self.cert.der().clone()).unwrap();
+      ChainVerifier {
+        roots: Arc::new(roots),
+        algorithms: ring::default_provider().signature_verification_algorithms,
+      }
+    }
+  }
+
+  fn verify(verifier: &ChainVerifier, cert: &CertificateDer<'_>) -> Result<(), rustls::Error> {
+    let name = ServerName::try_from("ignored").unwrap();
+    verifier
+      .verify_server_cert(cert, &[], &name, &[], UnixTime::now())
+      .map(|_| ())
+  }
+
+  #[test]
+  fn check_accepts_the_node_the_cert_is_issued_to() {
+    let ca = Ca::new();
+    let peer = PeerCert::Tls(Some(ca.issue("node-a")));
+    assert!(peer.check(&NodeId::from("node-a")).is_ok());
+  }
+
+  #[test]
+  fn check_rejects_another_node() {
+    let ca = Ca::new();
+    let peer = PeerCert::Tls(Some(ca.issue("node-a")));
+    assert!(peer.check(&NodeId::from("node-b")).is_err());
+  }
+
+  #[test]
+  fn check_rejects_a_peer_without_a_cert() {
+    assert!(PeerCert::Tls(None).check(&NodeId::from("node-a")).is_err());
+    assert!(PeerCert::Plain.check(&NodeId::from("node-a")).is_ok());
+  }
+
+  #[test]
+  fn verifier_accepts_certs_from_the_mesh_ca() {
+    let ca = Ca::new();
+    assert!(verify(&ca.verifier(), &ca.issue("node-a")).is_ok());
+  }
+
+  #[test]
+  fn verifier_rejects_certs_from_another_ca() {
+    let ca = Ca::new();
+    let other = Ca::new();
+    assert!(verify(&ca.verifier(), &other.issue("node-a")).is_err());
+  }
+}
@@ -0,0 +1,36 @@
+use crate::shutdown::container::ShutdownContainer;
+use std::time::Duration;
+use tokio::time::interval;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, instrument};
+
+#[instrument]
+pub async fn collect_tombstones(
+  container: &ShutdownContainer,
+  cancel: CancellationToken,
+) -> eyre::Result<()> {
+  let app = container.gossip_state.clone();
+  let grace = container.tombstone_grace;
+  let period = (grace / 4).clamp(Duration::from_secs(1), Duration::from_secs(60));
+  let mut interval = interval(period);
+  info!("Starting tombstone collection every {:?}...", period);
+  loop {
+    tokio::select! {
+      biased;
+      _ = cancel.cancelled() => {
+        debug!("Tombstone collection received shutdown");
+        break Ok(());
+      }
+      _ = interval.tick() => {
+        let buried = app.bury_nodes(grace).await;
+        if buried > 0 {
+          debug!("Tombstoned {} nodes dead or gone for over {:?}", buried, grace);
+        }
+        let purged = app.purge_tombstones(grace);
+        if purged > 0 {
+          debug!("Purged {} tombstones older than {:?}", purged, grace);
+        }
+      }
+    }
+  }
+}
@@ -1,3 +1,6 @@
+use super::auth::GossipAuth;
+use super::codec::Wire;
+use super::listener::Health;
 use super::state::{GossipPayload, GossipState};
 use crate::node::NodeId;
 use crate::shutdown::container::ShutdownContainer;
@@ -10,26 +13,57 @@ use tokio::time::interval;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, instrument, trace};
 
+/// Whether `id` answers health checks at `target_addr`, and if so, the wire
+/// format it advertises.
 #[instrument]
-pub async fn is_node_healthy(client: &Client, target_addr: &str) -> bool {
-  matches!(client
-    .get(format!("http://{}/health", target_addr))
+pub async fn check_health(
+  client: &Client,
+  auth: &GossipAuth,
+  id: &NodeId,
+  target_addr: &str,
+) -> Option<Wire> {
+  let response = client
+    .get(auth.url(target_addr, "/health"))
     .timeout(Duration::from_secs(1))
     .send()
-    .await, Ok(resp) if resp.status().is_success())
+    .await
+    .ok()?
+    .error_for_status()
+    .ok()?;
+  auth.check_peer(&response, id).ok()?;
+  // Nodes that predate reporting themselves only answer with a status.
+  let health = response.json::<Health>().await;
+  Some(health.map(|health| health.wire()).unwrap_or_default())
 }
 
+/// Finds an address `id` answers health checks at, trying the one that last
+/// worked first, and remembers it along with the wire format it takes.
 #[instrument]
-pub async fn build_gossip_payload(state: &GossipState) -> GossipPayload {
-  let dirty_keys = state.nodes().take_dirty().await;
-  let diffs: Vec<_> = dirty_keys
-    .into_iter()
-    .filter_map(|id| state.nodes().get(&id).map(|v| (id, v)))
-    .collect();
+pub async fn find_route(client: &Client, app: &GossipState, id: &NodeId) -> Option<String> {
+  let state = app.nodes().get(id)?;
+  for address in app.addresses_of(id, &state) {
+    if let Some(wire) = check_health(client, app.auth(), id, &address.to_string()).await {
+      app.record_route(id, address);
+      app.record_wire(id, wire);
+      return Some(address.to_string());
+    }
+  }
+  None
+}
 
+/// The most entries of each map a single gossip round carries; the rest
+/// wait for the next round.
+pub const GOSSIP_BATCH: usize = 256;
+
+#[instrument]
+pub async fn build_gossip_payload(state: &GossipState) -> GossipPayload {
   GossipPayload {
     from: state.id().clone(),
-    diffs,
+    cluster: state.cluster().to_string(),
+    clock: state.now(),
+    diffs: state.nodes().take_dirty_batch(GOSSIP_BATCH).await,
+    flags: state.flags().take_dirty_batch(GOSSIP_BATCH).await,
+    segments: state.segments().take_dirty_batch(GOSSIP_BATCH).await,
   }
 }
 
@@ -40,58 +74,65 @@ pub fn select_gossip_targets(app: &GossipState, count: usize) -> Vec<(NodeId, St
   let targets: Vec<_> = nodes
     .iter()
     .into_iter()
-    .filter(|entry| entry.0 != *my_id)
-    .map(|entry| (entry.0, entry.1.address().to_string()))
+    .filter(|entry| entry.0 != *my_id && entry.1.is_alive())
+    .map(|entry| {
+      let address = app.addresses_of(&entry.0, &entry.1)[0];
+      (entry.0, address.to_string())
+    })
     .collect();
   let mut rng = SmallRng::from_os_rng();
   targets.choose_multiple(&mut rng, count).cloned().collect()
 }
 
 #[instrument]
-async fn send_gossip(
+pub async fn send_gossip(
   client: &Client,
+  app: &GossipState,
+  id: &NodeId,
   target_addr: &str,
   payload: &GossipPayload,
 ) -> eyre::Result<()> {
-  let url = format!("http://{}/gossip", target_addr);
-  let payload_str = serde_json::to_string(payload)?;
-  client
-    .post(&url)
-    .body(payload_str)
-    .header("Content-Type", "application/json")
-    .send()
-    .await?
-    .error_for_status()?;
+  let auth = app.auth();
+  let url = auth.url(target_addr, "/gossip");
+  auth
+    .send(client.post(&url), id, app.wire_for(id), payload)
+    .await?;
   Ok(())
 }
 
+/// Sends one round of gossip to a few peers. A peer that is unhealthy at all
+/// of its addresses or refuses the payload is skipped; if none of them takes
+/// it, the payload goes back in the dirty sets for the next round.
 #[instrument]
 pub async fn gossip_tick(client: &Client, app: &GossipState) -> eyre::Result<()> {
-  {
-    let payload = build_gossip_payload(app).await;
-    if payload.diffs.is_empty() {
-      eyre::bail!("No gossip to send");
-    }
+  let targets = select_gossip_targets(app, 3);
+  if targets.is_empty() {
+    eyre::bail!("No gossip targets found");
+  }
 
-    let targets = select_gossip_targets(app, 3);
-    if targets.is_empty() {
-      eyre::bail!("No gossip targets found");
-    }
+  let payload = build_gossip_payload(app).await;
+  if payload.is_empty() {
+    eyre::bail!("No gossip to send");
+  }
 
-    for (id, address) in targets {
-      if !is_node_healthy(client, &address).await {
-        eyre::bail!("Node {} is not healthy", id);
-      }
+  let mut delivered = 0;
+  for (id, _) in targets {
+    let Some(address) = find_route(client, app, &id).await else {
+      debug!("Node {} is not healthy", id);
+      continue;
+    };
 
-      send_gossip(client, &address, &payload)
-        .await
-        .map_err(|error| {
-          debug!("Failed to send gossip to {}: {} ({:?})", id, error, error);
-          eyre::eyre!("Failed to send gossip to {}: {}", id, error)
-        })?;
+    match send_gossip(client, app, &id, &address, &payload).await {
+      Ok(()) => delivered += 1,
+      Err(error) => debug!("Failed to send gossip to {}: {} ({:?})", id, error, error),
     }
-    Ok(())
   }
+
+  if delivered == 0 {
+    app.requeue(payload).await;
+    eyre::bail!("No gossip target accepted the payload");
+  }
+  Ok(())
 }
 
 #[instrument]
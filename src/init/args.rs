@@ -1,6 +1,10 @@
 use super::socket::SocketStage;
+use crate::flags::conflict::ConflictMode;
+use crate::gossip::state::DEFAULT_CLUSTER;
+use crate::node::is_routable;
 use clap::Parser;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
 use tracing::{instrument, trace};
 
 const SERVICE_TYPE: &str = "_flags._tcp.local.";
@@ -8,7 +12,8 @@ const SERVICE_TYPE: &str = "_flags._tcp.local.";
 #[derive(Parser, Clone, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
-  /// IP address of the node; normally auto-detected
+  /// IP address (IPv4 or IPv6) or network interface of the node; normally
+  /// every routable address is advertised
   #[arg(short, long)]
   pub ip: Option<String>,
   /// UUID of the node; normally auto-generated
@@ -20,6 +25,57 @@ pub struct Args {
   /// The service type (domain, like "_flags._tcp.local.") to advertise
   #[arg(short, long, default_value_t = String::from(SERVICE_TYPE))]
   pub domain: String,
+  /// Name of the mesh to join; nodes of other clusters are ignored
+  #[arg(long, default_value_t = String::from(DEFAULT_CLUSTER))]
+  pub cluster: String,
+  /// Seconds to keep deletion tombstones before forgetting them
+  #[arg(long, default_value_t = 3600)]
+  pub tombstone_grace: u64,
+  /// Seconds a peer's clock may run ahead of this node's, and of every peer
+  /// heard from so far, before its gossip is ignored
+  #[arg(long, default_value_t = 60)]
+  pub max_clock_offset: u64,
+  /// How to treat concurrent edits of the same flag on different nodes
+  #[arg(long, value_enum, default_value_t = ConflictMode::Lww)]
+  pub conflict_mode: ConflictMode,
+  /// Don't browse for peers over mDNS
+  #[arg(long)]
+  pub no_mdns: bool,
+  /// Peer to join through, as host:port; may be repeated
+  #[arg(long = "seed", value_name = "HOST:PORT")]
+  pub seeds: Vec<String>,
+  /// DNS name to find peers under: host:port for A/AAAA records, otherwise
+  /// an SRV name; may be repeated
+  #[arg(long = "dns", value_name = "NAME")]
+  pub dns_names: Vec<String>,
+  /// Name server to ask for --dns names, as ip:port; normally the system's
+  #[arg(long)]
+  pub dns_resolver: Option<SocketAddr>,
+  /// Seconds between DNS lookups
+  #[arg(long, default_value_t = 30)]
+  pub dns_refresh: u64,
+  /// Keys to sign and verify gossip with, comma-separated; the first signs
+  /// and any verifies. Gossip is unauthenticated without keys
+  #[arg(
+    long,
+    env = "FLAGS_GOSSIP_KEYS",
+    value_delimiter = ',',
+    hide_env_values = true
+  )]
+  pub gossip_keys: Vec<String>,
+  /// File of further gossip keys, one per line
+  #[arg(long)]
+  pub gossip_key_file: Option<PathBuf>,
+  /// PEM bundle of the CA that issues node certificates; turns on mutual
+  /// TLS between nodes
+  #[arg(long, requires_all = ["tls_cert", "tls_key"])]
+  pub tls_ca: Option<PathBuf>,
+  /// PEM certificate of this node, naming its ID as a DNS name
+  #[arg(long, requires = "tls_ca")]
+  pub tls_cert: Option<PathBuf>,
+  /// PEM private key of this node's certificate
+  #[arg(long, requires = "tls_ca")]
+  pub tls_key: Option<PathBuf>,
 }
 
 #[derive(Debug)]
@@ -40,22 +96,43 @@ impl ArgsStage {
     Ok(Self { args })
   }
 
+  /// Picks the addresses to advertise. An IP address is listened on alone;
+  /// otherwise the node listens on every interface, dual-stack, and
+  /// advertises the routable addresses of the named interface, or of all.
   #[instrument]
   pub fn bind_socket(self) -> eyre::Result<SocketStage> {
-    let ip = match &self.args.ip {
-      Some(ip) => ip.parse()?,
-      None => {
-        if let Ok(IpAddr::V4(ip)) = local_ip_address::local_ip() {
-          ip
-        } else {
-          return Err(eyre::eyre!("Could not get local IP address"));
-        }
-      },
+    let (ip, addresses) = match self.args.ip.as_deref().map(str::parse::<IpAddr>) {
+      Some(Ok(ip)) if !ip.is_unspecified() => (ip, vec![ip]),
+      Some(Ok(_)) => (Ipv6Addr::UNSPECIFIED.into(), local_addresses(None)?),
+      Some(Err(_)) => (
+        Ipv6Addr::UNSPECIFIED.into(),
+        local_addresses(self.args.ip.as_deref())?,
+      ),
+      None => (Ipv6Addr::UNSPECIFIED.into(), local_addresses(None)?),
     };
 
     Ok(SocketStage {
       args: self.args,
       ip,
+      addresses,
     })
   }
 }
+
+/// The routable addresses of `interface`, or of every interface, IPv4 first.
+fn local_addresses(interface: Option<&str>) -> eyre::Result<Vec<IpAddr>> {
+  let mut addresses: Vec<IpAddr> = local_ip_address::list_afinet_netifas()?
+    .into_iter()
+    .filter(|(name, ip)| interface.is_none_or(|interface| name == interface) && is_routable(ip))
+    .map(|(_, ip)| ip)
+    .collect();
+  addresses.sort_by_key(|ip| (ip.is_ipv6(), *ip));
+  addresses.dedup();
+  if addresses.is_empty() {
+    match interface {
+      Some(interface) => eyre::bail!("No routable IP address on interface {}", interface),
+      None => eyre::bail!("Could not get local IP address"),
+    }
+  }
+  Ok(addresses)
+}
@@ -1,29 +1,66 @@
 use super::container::ContainerStage;
+use crate::discovery::backend::DiscoveryConfig;
+use crate::flags::conflict::ConflictMode;
+use crate::gossip::auth::GossipKeys;
+use crate::gossip::codec::Wire;
+use crate::gossip::tls::MeshTls;
 use crate::node::NodeId;
 use mdns_sd::ServiceInfo;
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 use tokio::net::TcpListener;
 
 #[derive(Clone, Debug)]
 pub struct Config {
   pub id: NodeId,
   pub domain: String,
+  pub cluster: String,
   pub socket_addr: SocketAddr,
+  pub addresses: Vec<SocketAddr>,
   pub properties: HashMap<String, String>,
+  pub tombstone_grace: Duration,
+  pub max_clock_offset: Duration,
+  pub conflict_mode: ConflictMode,
+  pub gossip_keys: GossipKeys,
+  pub tls: Option<MeshTls>,
+  pub discovery: DiscoveryConfig,
 }
 
 impl Config {
-  pub fn new(id: &NodeId, domain: &str, socket_addr: SocketAddr) -> Self {
+  #[allow(clippy::too_many_arguments)]
+  pub fn new(
+    id: &NodeId,
+    domain: &str,
+    cluster: &str,
+    socket_addr: SocketAddr,
+    addresses: Vec<SocketAddr>,
+    tombstone_grace: Duration,
+    max_clock_offset: Duration,
+    conflict_mode: ConflictMode,
+    gossip_keys: GossipKeys,
+    tls: Option<MeshTls>,
+    discovery: DiscoveryConfig,
+  ) -> Self {
     let id = id.clone();
     let domain = domain.to_string();
+    let cluster = cluster.to_string();
     let properties = HashMap::new();
 
     Self {
       id,
       domain,
+      cluster,
       socket_addr,
+      addresses,
       properties,
+      tombstone_grace,
+      max_clock_offset,
+      conflict_mode,
+      gossip_keys,
+      tls,
+      discovery,
     }
   }
 
@@ -32,15 +69,23 @@ impl Config {
 
     let mut properties = self.properties.clone();
     properties.insert("node.id".to_string(), self.id.clone().into());
+    properties.insert("node.cluster".to_string(), self.cluster.clone());
     properties.insert("node.ip".to_string(), socket_addr.ip().to_string());
     properties.insert("node.port".to_string(), socket_addr.port().to_string());
     properties.insert("node.address".to_string(), socket_addr.to_string());
+    properties.insert("node.addresses".to_string(), join(&self.addresses));
+    properties.insert("node.codecs".to_string(), Wire::codecs().join(","));
+    properties.insert(
+      "node.compressions".to_string(),
+      Wire::compressions().join(","),
+    );
 
+    let ips: Vec<_> = self.addresses.iter().map(SocketAddr::ip).collect();
     let service_info = ServiceInfo::new(
       &self.domain,
       &String::from(self.id.clone()),
       &format!("{}.local.", self.id),
-      socket_addr.ip().to_string(),
+      join(&ips),
       socket_addr.port(),
       properties.clone(),
     )
@@ -49,16 +94,48 @@ impl Config {
   }
 }
 
+fn join<T: ToString>(items: &[T]) -> String {
+  items.iter().map(T::to_string).collect::<Vec<_>>().join(",")
+}
+
 pub struct ConfigStage {
   pub id: NodeId,
   pub domain: String,
+  pub cluster: String,
   pub listener: TcpListener,
   pub socket_addr: SocketAddr,
+  pub addresses: Vec<SocketAddr>,
+  pub tombstone_grace: Duration,
+  pub max_clock_offset: Duration,
+  pub conflict_mode: ConflictMode,
+  pub gossip_keys: Vec<String>,
+  pub gossip_key_file: Option<PathBuf>,
+  pub tls_ca: Option<PathBuf>,
+  pub tls_cert: Option<PathBuf>,
+  pub tls_key: Option<PathBuf>,
+  pub discovery: DiscoveryConfig,
 }
 
 impl ConfigStage {
   pub fn build(self) -> eyre::Result<ContainerStage> {
-    let config = Config::new(&self.id, &self.domain, self.socket_addr);
+    let gossip_keys = GossipKeys::load(&self.gossip_keys, self.gossip_key_file.as_deref())?;
+    let tls = match (&self.tls_ca, &self.tls_cert, &self.tls_key) {
+      (Some(ca), Some(cert), Some(key)) => Some(MeshTls::load(ca, cert, key)?),
+      _ => None,
+    };
+    let config = Config::new(
+      &self.id,
+      &self.domain,
+      &self.cluster,
+      self.socket_addr,
+      self.addresses,
+      self.tombstone_grace,
+      self.max_clock_offset,
+      self.conflict_mode,
+      gossip_keys,
+      tls,
+      self.discovery,
+    );
     let service_info = config.service_info()?;
     Ok(ContainerStage {
       config,
@@ -14,10 +14,23 @@ pub struct ContainerStage {
 
 impl ContainerStage {
   pub fn finalize(self) -> (ShutdownContainer, TcpListener) {
-    let gossip_state = GossipState::new(&self.config.id);
+    let gossip_state = GossipState::new(
+      &self.config.id,
+      &self.config.cluster,
+      self.config.conflict_mode,
+      self.config.gossip_keys.clone(),
+      self.config.tls.clone(),
+      self.config.max_clock_offset,
+    );
     let service_daemon = ServiceDaemon::new().expect("Failed to create service daemon");
     let domain = self.config.domain.clone();
-    let client = ClientBuilder::new()
+    let mut client = ClientBuilder::new();
+    if let Some(tls) = &self.config.tls {
+      client = client
+        .use_preconfigured_tls(tls.client_config())
+        .tls_info(true);
+    }
+    let client = client
       .timeout(Duration::from_secs(5))
       .connect_timeout(Duration::from_secs(1))
       .pool_idle_timeout(Duration::from_secs(1))
@@ -32,7 +45,10 @@ impl ContainerStage {
       service_daemon,
       domain,
       self.service_info,
+      self.config.addresses.clone(),
+      self.config.discovery.backends(),
       client,
+      self.config.tombstone_grace,
     );
 
     (container, self.listener)
@@ -1,13 +1,16 @@
 use super::args::Args;
 use super::config::ConfigStage;
+use crate::discovery::backend::DiscoveryConfig;
 use crate::node::NodeId;
 use std::net::SocketAddr;
+use std::time::Duration;
 use tokio::net::TcpListener;
 
 pub struct IdentityStage {
   pub args: Args,
   pub listener: TcpListener,
   pub socket_addr: SocketAddr,
+  pub addresses: Vec<SocketAddr>,
 }
 
 impl IdentityStage {
@@ -20,8 +23,25 @@ impl IdentityStage {
     ConfigStage {
       id,
       domain: self.args.domain.clone(),
+      cluster: self.args.cluster.clone(),
       listener: self.listener,
       socket_addr: self.socket_addr,
+      addresses: self.addresses,
+      tombstone_grace: Duration::from_secs(self.args.tombstone_grace),
+      max_clock_offset: Duration::from_secs(self.args.max_clock_offset),
+      conflict_mode: self.args.conflict_mode,
+      gossip_keys: self.args.gossip_keys.clone(),
+      gossip_key_file: self.args.gossip_key_file.clone(),
+      tls_ca: self.args.tls_ca.clone(),
+      tls_cert: self.args.tls_cert.clone(),
+      tls_key: self.args.tls_key.clone(),
+      discovery: DiscoveryConfig {
+        mdns: !self.args.no_mdns,
+        seeds: self.args.seeds.clone(),
+        dns_names: self.args.dns_names.clone(),
+        dns_resolver: self.args.dns_resolver,
+        dns_refresh: Duration::from_secs(self.args.dns_refresh),
+      },
     }
   }
 }
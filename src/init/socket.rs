@@ -1,22 +1,59 @@
 use super::args::Args;
 use super::identity::IdentityStage;
-use std::net::{Ipv4Addr, SocketAddr};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use tokio::net::TcpListener;
+use tracing::debug;
 
 pub struct SocketStage {
   pub args: Args,
-  pub ip: Ipv4Addr,
+  /// The address to listen on; unspecified to listen on every interface.
+  pub ip: IpAddr,
+  /// The addresses to advertise.
+  pub addresses: Vec<IpAddr>,
 }
 
 impl SocketStage {
   pub async fn bind(self) -> eyre::Result<IdentityStage> {
-    let addr = SocketAddr::new(self.ip.into(), self.args.port);
-    let listener = TcpListener::bind(addr).await?;
-    let socket_addr = listener.local_addr()?;
+    let addr = SocketAddr::new(self.ip, self.args.port);
+    let listener = if self.ip.is_unspecified() {
+      match bind_dual_stack(addr) {
+        Ok(listener) => listener,
+        Err(error) => {
+          debug!("Listening on IPv4 only: {}", error);
+          TcpListener::bind(SocketAddr::new(
+            Ipv4Addr::UNSPECIFIED.into(),
+            self.args.port,
+          ))
+          .await?
+        },
+      }
+    } else {
+      TcpListener::bind(addr).await?
+    };
+    let port = listener.local_addr()?.port();
+    let addresses: Vec<SocketAddr> = self
+      .addresses
+      .iter()
+      .map(|ip| SocketAddr::new(*ip, port))
+      .collect();
     Ok(IdentityStage {
       args: self.args,
       listener,
-      socket_addr,
+      socket_addr: addresses[0],
+      addresses,
     })
   }
 }
+
+/// Listens on every interface for both IPv4 and IPv6, whatever the system's
+/// default for IPv6 sockets is.
+fn bind_dual_stack(addr: SocketAddr) -> eyre::Result<TcpListener> {
+  let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+  socket.set_only_v6(false)?;
+  socket.set_reuse_address(true)?;
+  socket.set_nonblocking(true)?;
+  socket.bind(&addr.into())?;
+  socket.listen(1024)?;
+  Ok(TcpListener::from_std(socket.into())?)
+}
@@ -1,9 +1,14 @@
+use gossip::leave;
 use init::args::ArgsStage;
 use shutdown::manager::ShutdownManager;
 use tokio::signal;
 use tracing::{info, instrument};
 
+mod api;
 mod crdts;
+mod discovery;
+mod evaluation;
+mod flags;
 mod gossip;
 mod init;
 mod log;
@@ -28,14 +33,17 @@ async fn main() -> eyre::Result<()> {
     .generate_id()
     .build()?
     .finalize();
+  container.gossip_state.add_self(&container.addresses).await;
   container.register_tasks(&shutdown, listener).await;
 
   shutdown
     .spawn("ctrl_c", {
       let shutdown = shutdown.clone();
+      let container = container.clone();
       async move {
         signal::ctrl_c().await.expect("failed to listen for event");
         info!("Ctrl-C pressed, shutting down...");
+        leave::announce_leave(&container).await;
         shutdown.cancel();
       }
     })
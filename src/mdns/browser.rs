@@ -1,9 +1,11 @@
-use crate::gossip::state::GossipState;
-use crate::node::{NodeId, NodeState};
+use crate::discovery::backend::Discovery;
+use crate::gossip::codec::Wire;
+use crate::gossip::state::{GossipState, default_cluster};
+use crate::node::{NodeId, NodeState, is_routable};
 use crate::shutdown::container::ShutdownContainer;
-use mdns_sd::{IfKind, ServiceEvent, ServiceInfo};
+use futures::future::BoxFuture;
+use mdns_sd::{ServiceEvent, ServiceInfo};
 use std::net::SocketAddr;
-use std::time::{SystemTime, UNIX_EPOCH};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, instrument, trace};
 
@@ -52,13 +54,16 @@ impl BrowserDelegate {
   pub async fn did_resolve_service(&mut self, service_info: ServiceInfo) -> eyre::Result<()> {
     debug!("Resolved service: {:?}", service_info);
     let id = service_info.get_node_id()?;
-    let socket_addr = service_info.get_socket_addr()?;
-    let last_seen = SystemTime::now()
-      .duration_since(UNIX_EPOCH)
-      .expect("Time went backwards")
-      .as_secs();
-    let node_state = NodeState::new(&id, last_seen, socket_addr);
+    let cluster = service_info.get_cluster();
+    if cluster != self.gossip_state.cluster() {
+      trace!("Ignoring {} of cluster {}", id, cluster);
+      return Ok(());
+    }
+    let socket_addrs = service_info.get_socket_addrs()?;
+    let last_seen = self.gossip_state.now();
+    let node_state = NodeState::new(&id, last_seen, socket_addrs[0]).with_addresses(socket_addrs);
     self.gossip_state.add_node(&id, node_state).await;
+    self.gossip_state.record_wire(&id, service_info.get_wire());
     Ok(())
   }
 
@@ -81,7 +86,9 @@ impl BrowserDelegate {
 
 pub trait ServiceInfoExt {
   fn get_node_id(&self) -> eyre::Result<NodeId>;
-  fn get_socket_addr(&self) -> eyre::Result<SocketAddr>;
+  fn get_cluster(&self) -> String;
+  fn get_socket_addrs(&self) -> eyre::Result<Vec<SocketAddr>>;
+  fn get_wire(&self) -> Wire;
 }
 
 impl ServiceInfoExt for ServiceInfo {
@@ -94,15 +101,56 @@ impl ServiceInfoExt for ServiceInfo {
       .ok_or_else(|| eyre::eyre!("Node ID not found in service properties"))
   }
 
-  fn get_socket_addr(&self) -> eyre::Result<SocketAddr> {
-    let ip = self
-      .get_addresses_v4()
-      .iter()
-      .cloned()
-      .next()
-      .ok_or_else(|| eyre::eyre!("IP address not found in service info"))?;
+  fn get_cluster(&self) -> String {
+    self
+      .get_properties()
+      .get("node.cluster")
+      .map(|v| v.val_str().to_string())
+      .unwrap_or_else(default_cluster)
+  }
+
+  /// The addresses the node advertises in its properties, in its order of
+  /// preference, or else the routable ones it resolved to.
+  fn get_socket_addrs(&self) -> eyre::Result<Vec<SocketAddr>> {
+    let advertised = self
+      .get_properties()
+      .get("node.addresses")
+      .map(|v| v.val_str())
+      .unwrap_or_default()
+      .split(',')
+      .filter_map(|address| address.parse().ok())
+      .collect::<Vec<SocketAddr>>();
+    if !advertised.is_empty() {
+      return Ok(advertised);
+    }
     let port = self.get_port();
-    Ok(SocketAddr::new((*ip).into(), port))
+    let mut addresses: Vec<SocketAddr> = self
+      .get_addresses()
+      .iter()
+      .filter(|ip| is_routable(ip))
+      .map(|ip| SocketAddr::new(*ip, port))
+      .collect();
+    if addresses.is_empty() {
+      eyre::bail!("IP address not found in service info");
+    }
+    addresses.sort_by_key(|address| (address.is_ipv6(), *address));
+    Ok(addresses)
+  }
+
+  /// The best wire format among those the node advertises; plain JSON for
+  /// nodes that predate advertising them.
+  fn get_wire(&self) -> Wire {
+    let property = |key| {
+      self
+        .get_properties()
+        .get(key)
+        .map(|v| v.val_str())
+        .unwrap_or_default()
+    };
+    Wire::negotiate(
+      property("node.codecs").split(','),
+      property("node.compressions").split(','),
+    )
   }
 }
 
@@ -114,7 +162,6 @@ pub async fn browse_loop(
   let service_daemon = &container.service_daemon;
   let service_type = &container.domain;
   let mut delegate = BrowserDelegate::new(gossip_state);
-  service_daemon.disable_interface(IfKind::IPv6)?;
   let receiver = service_daemon.browse(service_type)?;
   loop {
     tokio::select! {
@@ -134,3 +181,21 @@ pub async fn browse_loop(
     }
   }
 }
+
+/// Finds peers by browsing for the service type over mDNS.
+#[derive(Debug)]
+pub struct MdnsDiscovery;
+
+impl Discovery for MdnsDiscovery {
+  fn name(&self) -> &'static str {
+    "browse_services"
+  }
+
+  fn discover<'a>(
+    &'a self,
+    container: &'a ShutdownContainer,
+    cancel_token: CancellationToken,
+  ) -> BoxFuture<'a, eyre::Result<()>> {
+    Box::pin(browse_loop(container, cancel_token))
+  }
+}
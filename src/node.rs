@@ -1,10 +1,11 @@
+use super::crdts::hlc::Hlc;
 use super::crdts::last_write_wins::LastWriteWins;
 use serde::{Deserialize, Serialize};
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, SocketAddr};
 use tracing::instrument;
 use uuid::Uuid;
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Serialize, Deserialize)]
 pub struct NodeId(String);
 
 impl NodeId {
@@ -46,23 +47,58 @@ impl From<NodeId> for String {
   }
 }
 
+/// What the mesh believes about a node. Ordered so that, at the same
+/// incarnation, the more pessimistic belief wins.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeStatus {
+  Alive,
+  /// Missed a direct and an indirect probe; declared dead unless it refutes
+  /// the suspicion in time.
+  Suspect,
+  Dead,
+  /// Announced by the node itself on its way out.
+  Left,
+}
+
 #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub struct NodeState {
   id: NodeId,
-  last_seen: u64,
+  last_seen: Hlc,
   address: SocketAddr,
+  /// Every address the node advertises, `address` among them; empty for
+  /// nodes found through a single address.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  addresses: Vec<SocketAddr>,
+  #[serde(default = "alive")]
+  status: NodeStatus,
+  /// Raised only by the node itself, to refute suspicion of it.
+  #[serde(default)]
+  incarnation: u64,
+}
+
+fn alive() -> NodeStatus {
+  NodeStatus::Alive
 }
 
 impl NodeState {
-  pub fn new(id: &NodeId, last_seen: u64, address: SocketAddr) -> Self {
+  pub fn new(id: &NodeId, last_seen: Hlc, address: SocketAddr) -> Self {
     let id = id.clone();
     Self {
       id,
       last_seen,
       address,
+      addresses: Vec::new(),
+      status: NodeStatus::Alive,
+      incarnation: 0,
     }
   }
 
+  /// The same node, reachable at any of `addresses` as well.
+  pub fn with_addresses(self, addresses: Vec<SocketAddr>) -> Self {
+    Self { addresses, ..self }
+  }
+
   pub fn id(&self) -> &NodeId {
     &self.id
   }
@@ -71,30 +107,150 @@ impl NodeState {
     &self.address
   }
 
+  /// Every address the node can be reached at, its preferred one first.
+  pub fn addresses(&self) -> Vec<SocketAddr> {
+    let mut addresses = vec![self.address];
+    let others = self
+      .addresses
+      .iter()
+      .filter(|address| **address != self.address);
+    addresses.extend(others);
+    addresses
+  }
+
   #[instrument]
-  pub fn ip(&self) -> eyre::Result<Ipv4Addr> {
-    let ip = self.address.ip();
-    if let IpAddr::V4(ipv4) = ip {
-      return Ok(ipv4);
-    }
-    eyre::bail!("NodeState::ip() - IP address is not IPv4")
+  pub fn ip(&self) -> IpAddr {
+    self.address.ip()
   }
 
   pub fn port(&self) -> u16 {
     self.address.port()
   }
 
-  pub fn last_seen(&self) -> u64 {
-    self.last_seen
+  pub fn last_seen(&self) -> &Hlc {
+    &self.last_seen
   }
 
-  pub fn set_last_seen(&mut self, last_seen: u64) {
+  pub fn set_last_seen(&mut self, last_seen: Hlc) {
     self.last_seen = last_seen;
   }
+
+  pub fn status(&self) -> NodeStatus {
+    self.status
+  }
+
+  pub fn incarnation(&self) -> u64 {
+    self.incarnation
+  }
+
+  pub fn is_alive(&self) -> bool {
+    self.status == NodeStatus::Alive
+  }
+
+  /// The same node at the same incarnation, believed to be in `status`.
+  pub fn with_status(&self, status: NodeStatus, last_seen: Hlc) -> Self {
+    Self {
+      status,
+      last_seen,
+      ..self.clone()
+    }
+  }
+
+  /// The node's own answer to suspicion at `incarnation`: alive, at an
+  /// incarnation that beats it.
+  pub fn refute(&self, incarnation: u64, last_seen: Hlc) -> Self {
+    Self {
+      status: NodeStatus::Alive,
+      incarnation: incarnation.max(self.incarnation) + 1,
+      last_seen,
+      ..self.clone()
+    }
+  }
 }
 
 impl LastWriteWins for NodeState {
+  fn timestamp(&self) -> &Hlc {
+    &self.last_seen
+  }
+
+  /// A higher incarnation always wins. At the same incarnation the more
+  /// pessimistic status wins, and only then the newer write.
   fn is_newer_than(&self, other: &Self) -> bool {
-    self.last_seen > other.last_seen
+    (self.incarnation, self.status, &self.last_seen)
+      > (other.incarnation, other.status, &other.last_seen)
+  }
+}
+
+/// Whether a peer could dial an address: not loopback, unspecified or
+/// multicast, and not IPv6 link-local, which needs a scope to be dialled.
+pub fn is_routable(ip: &IpAddr) -> bool {
+  let scoped = matches!(ip, IpAddr::V6(ip) if ip.is_unicast_link_local());
+  !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || scoped)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn at(physical_ms: u64) -> Hlc {
+    Hlc {
+      physical_ms,
+      logical: 0,
+      node: NodeId::from("observer"),
+    }
+  }
+
+  fn node(incarnation: u64, status: NodeStatus, last_seen: u64) -> NodeState {
+    let state = NodeState::new(&NodeId::from("a"), at(0), "10.0.0.1:7000".parse().unwrap());
+    NodeState {
+      incarnation,
+      ..state
+    }
+    .with_status(status, at(last_seen))
+  }
+
+  #[test]
+  fn a_higher_incarnation_wins_over_any_status_and_time() {
+    let alive = node(2, NodeStatus::Alive, 10);
+    for status in [NodeStatus::Suspect, NodeStatus::Dead, NodeStatus::Left] {
+      let older = node(1, status, 20);
+      assert!(alive.is_newer_than(&older), "{:?}", status);
+      assert!(!older.is_newer_than(&alive), "{:?}", status);
+    }
+  }
+
+  #[test]
+  fn at_the_same_incarnation_the_more_pessimistic_status_wins() {
+    let statuses = [
+      NodeStatus::Alive,
+      NodeStatus::Suspect,
+      NodeStatus::Dead,
+      NodeStatus::Left,
+    ];
+    for pair in statuses.windows(2) {
+      let better = node(3, pair[0], 20);
+      let worse = node(3, pair[1], 10);
+      assert!(worse.is_newer_than(&better), "{:?}", pair);
+      assert!(!better.is_newer_than(&worse), "{:?}", pair);
+    }
+  }
+
+  #[test]
+  fn at_the_same_incarnation_and_status_the_newer_write_wins() {
+    let older = node(1, NodeStatus::Suspect, 10);
+    let newer = node(1, NodeStatus::Suspect, 11);
+    assert!(newer.is_newer_than(&older));
+    assert!(!older.is_newer_than(&newer));
+  }
+
+  #[test]
+  fn a_refutation_beats_the_suspicion_it_answers() {
+    for status in [NodeStatus::Suspect, NodeStatus::Dead] {
+      let suspected = node(4, status, 20);
+      let refuted = node(0, NodeStatus::Alive, 0).refute(suspected.incarnation(), at(5));
+      assert_eq!(refuted.status(), NodeStatus::Alive);
+      assert_eq!(refuted.incarnation(), 5);
+      assert!(refuted.is_newer_than(&suspected), "{:?}", status);
+    }
   }
 }
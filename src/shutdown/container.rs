@@ -1,12 +1,16 @@
 use crate::{
-  gossip::{listener, state::GossipState, whisperer},
-  mdns::{browser, register},
+  discovery::backend::Discovery,
+  gossip::{anti_entropy, listener, state::GossipState, swim, tombstones, whisperer},
+  mdns::register,
   shutdown::manager::ShutdownManager,
 };
 use derivative::Derivative;
 use futures::future::BoxFuture;
 use mdns_sd::{ServiceDaemon, ServiceInfo};
 use reqwest::Client;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio_util::sync::CancellationToken;
 
 pub type ShutdownTaskReturn = BoxFuture<'static, eyre::Result<()>>;
@@ -22,23 +26,33 @@ pub struct ShutdownContainer {
   pub service_daemon: ServiceDaemon,
   pub domain: String,
   pub service_info: ServiceInfo,
+  pub addresses: Vec<SocketAddr>,
+  pub discovery: Vec<Arc<dyn Discovery>>,
   pub http_client: Client,
+  pub tombstone_grace: Duration,
 }
 
 impl ShutdownContainer {
+  #[allow(clippy::too_many_arguments)]
   pub fn new(
     gossip_state: GossipState,
     service_daemon: ServiceDaemon,
     domain: String,
     service_info: ServiceInfo,
+    addresses: Vec<SocketAddr>,
+    discovery: Vec<Arc<dyn Discovery>>,
     http_client: Client,
+    tombstone_grace: Duration,
   ) -> Self {
     Self {
       gossip_state,
       service_daemon,
       domain,
       service_info,
+      addresses,
+      discovery,
       http_client,
+      tombstone_grace,
     }
   }
 
@@ -48,12 +62,6 @@ impl ShutdownContainer {
     listener: tokio::net::TcpListener,
   ) {
     let tasks: Vec<(&'static str, ShutdownTask)> = vec![
-      (
-        "browse_services",
-        Box::new(|cancel, container| {
-          Box::pin(async move { browser::browse_loop(&container, cancel).await })
-        }),
-      ),
       (
         "register_service",
         Box::new(|cancel, container| {
@@ -72,11 +80,38 @@ impl ShutdownContainer {
           Box::pin(async move { whisperer::gossip_whisper(&container, cancel).await })
         }),
       ),
+      (
+        "swim_probe",
+        Box::new(|cancel, container| {
+          Box::pin(async move { swim::swim_probe(&container, cancel).await })
+        }),
+      ),
+      (
+        "anti_entropy",
+        Box::new(|cancel, container| {
+          Box::pin(async move { anti_entropy::anti_entropy(&container, cancel).await })
+        }),
+      ),
+      (
+        "tombstone_gc",
+        Box::new(|cancel, container| {
+          Box::pin(async move { tombstones::collect_tombstones(&container, cancel).await })
+        }),
+      ),
     ];
 
     for (name, task) in tasks {
       self.spawn(shutdown, name, task).await;
     }
+
+    for backend in &self.discovery {
+      let backend = backend.clone();
+      self
+        .spawn(shutdown, backend.name(), |cancel, container| async move {
+          backend.discover(&container, cancel).await
+        })
+        .await;
+    }
   }
 
   pub async fn spawn<F, Fut>(&self, shutdown: &ShutdownManager, name: &'static str, f: F)